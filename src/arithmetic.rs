@@ -0,0 +1,463 @@
+//! Integer arithmetic, as evaluated by `$(( expr ))`. Parameters, command substitutions... are expanded before.
+//!
+//! Operators, from the lowest precedence to the highest, like in C:
+//! * `,` sequence, the value is the last one
+//! * `=` `+=` `-=` `*=` `/=` `%=` `<<=` `>>=` `&=` `^=` `|=` assignment, to a variable
+//! * `?:` conditional
+//! * `||`, `&&` logical, the right side is only evaluated if needed
+//! * `|`, `^`, `&` bitwise
+//! * `==` `!=`, `<` `<=` `>` `>=` comparison, 1 if true, 0 otherwise
+//! * `<<` `>>` shifts, `+` `-`, `*` `/` `%`, `**` power
+//! * `!` `~` `-` `+` unary, `++` `--` increment / decrement before or after a variable
+//!
+//! Numbers are 64-bit signed integers, in decimal, hexadecimal `0x1F` or octal `017`. Variables can be referenced by
+//! name, their value is itself evaluated, and an unset or empty one counts as 0
+use crate::{input_handler::ExpansionError, state};
+use std::convert::TryFrom;
+
+/// How deep variables can refer to other variables, e.g. `a=b b=a`
+const MAX_DEPTH: usize = 64;
+
+/// Binary operators, one group per precedence level, the lowest first. `**` is handled apart as it groups to the right
+const BINARY_OPERATORS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// Every operator, the longest first so they are split properly
+const OPERATORS: [&str; 36] = [
+    "<<=", ">>=", "**", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "++", "--", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "|", "^", "!", "~",
+    "?", ":", "=",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+    OpenParen,
+    CloseParen,
+    Comma,
+}
+
+/// Evaluates the arithmetic expression `expr`, applying its assignments
+pub fn evaluate(expr: &str) -> Result<i64, ExpansionError> {
+    evaluate_at(expr, 0)
+}
+
+fn evaluate_at(expr: &str, depth: usize) -> Result<i64, ExpansionError> {
+    if depth > MAX_DEPTH {
+        return Err(error(expr, "expression recursion level exceeded"));
+    }
+    let mut evaluator = Evaluator {
+        expr,
+        tokens: tokenize(expr)?,
+        pos: 0,
+        depth,
+    };
+    //An empty expression, e.g. `$(())`, is 0
+    if evaluator.tokens.is_empty() {
+        return Ok(0);
+    }
+    let value = evaluator.sequence(true)?;
+    match evaluator.tokens.get(evaluator.pos) {
+        None => Ok(value),
+        Some(token) => Err(error(expr, &format!("syntax error near `{}`", token))),
+    }
+}
+
+fn error(expr: &str, msg: &str) -> ExpansionError {
+    ExpansionError::Arithmetic(format!("{}: {}", expr.trim(), msg))
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, ExpansionError> {
+    let mut tokens = vec![];
+    let mut rest = expr.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(number(expr, &rest[..len])?));
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..len].to_owned()));
+            len
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Operator(op));
+            op.len()
+        } else {
+            tokens.push(match c {
+                '(' => Token::OpenParen,
+                ')' => Token::CloseParen,
+                ',' => Token::Comma,
+                _ => return Err(error(expr, &format!("syntax error near `{}`", c))),
+            });
+            1
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Parses a number in decimal, hexadecimal (`0x`) or octal (leading `0`)
+fn number(expr: &str, digits: &str) -> Result<i64, ExpansionError> {
+    let parsed = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse()
+    };
+    parsed.map_err(|e| {
+        let msg = match e.kind() {
+            std::num::IntErrorKind::PosOverflow => "value too great for base",
+            _ => "invalid number",
+        };
+        error(expr, &format!("{} (error token is `{}`)", msg, digits))
+    })
+}
+
+/// Recursive-descent evaluator. Each method evaluates a precedence level, `eval` is false on the side of `&&`, `||`
+/// and `?:` that isn't taken, which is still parsed but has no effect, and can't fail because of division by 0...
+struct Evaluator<'a> {
+    expr: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    /// Consumes the next token if it's the operator `op`
+    fn next_if_operator(&mut self, op: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Operator(o)) if *o == op);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn error(&self, msg: &str) -> ExpansionError {
+        error(self.expr, msg)
+    }
+
+    fn unexpected(&self) -> ExpansionError {
+        match self.peek() {
+            Some(token) => self.error(&format!("syntax error near `{}`", token)),
+            None => self.error("syntax error: operand expected"),
+        }
+    }
+
+    /// `a, b`
+    fn sequence(&mut self, eval: bool) -> Result<i64, ExpansionError> {
+        let mut value = self.assignment(eval)?;
+        while self.peek() == Some(&Token::Comma) {
+            self.pos += 1;
+            value = self.assignment(eval)?;
+        }
+        Ok(value)
+    }
+
+    /// `name = value`, `name += value`...
+    fn assignment(&mut self, eval: bool) -> Result<i64, ExpansionError> {
+        if let (Some(Token::Name(name)), Some(Token::Operator(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+        {
+            if op.ends_with('=') && !matches!(*op, "==" | "!=" | "<=" | ">=") {
+                let (name, op) = (name.clone(), *op);
+                self.pos += 2;
+                let value = self.assignment(eval)?;
+                if !eval {
+                    return Ok(0);
+                }
+                let value = match &op[..op.len() - 1] {
+                    "" => value,
+                    op => self.apply(op, self.variable(&name)?, value)?,
+                };
+                self.assign(&name, value)?;
+                return Ok(value);
+            }
+        }
+        self.conditional(eval)
+    }
+
+    /// `condition ? a : b`
+    fn conditional(&mut self, eval: bool) -> Result<i64, ExpansionError> {
+        let condition = self.binary(0, eval)?;
+        if !self.next_if_operator("?") {
+            return Ok(condition);
+        }
+        let if_true = self.sequence(eval && condition != 0)?;
+        if !self.next_if_operator(":") {
+            return Err(self.unexpected());
+        }
+        let if_false = self.conditional(eval && condition == 0)?;
+        Ok(if condition != 0 { if_true } else { if_false })
+    }
+
+    /// Left-associative binary operators, from `BINARY_OPERATORS[level]` on
+    fn binary(&mut self, level: usize, eval: bool) -> Result<i64, ExpansionError> {
+        if level == BINARY_OPERATORS.len() {
+            return self.power(eval);
+        }
+        let mut value = self.binary(level + 1, eval)?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Operator(op)) if BINARY_OPERATORS[level].contains(op) => *op,
+                _ => return Ok(value),
+            };
+            self.pos += 1;
+            //The right side of `&&` and `||` is only evaluated if it can change the result
+            let eval_rhs = match op {
+                "&&" => eval && value != 0,
+                "||" => eval && value == 0,
+                _ => eval,
+            };
+            let rhs = self.binary(level + 1, eval_rhs)?;
+            value = if eval { self.apply(op, value, rhs)? } else { 0 };
+        }
+    }
+
+    /// `a ** b`, right-associative
+    fn power(&mut self, eval: bool) -> Result<i64, ExpansionError> {
+        let base = self.unary(eval)?;
+        if !self.next_if_operator("**") {
+            return Ok(base);
+        }
+        let exponent = self.power(eval)?;
+        if eval {
+            self.apply("**", base, exponent)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// `!a`, `~a`, `-a`, `+a`, `++name`, `--name`
+    fn unary(&mut self, eval: bool) -> Result<i64, ExpansionError> {
+        let op = match self.peek() {
+            Some(Token::Operator(op)) if matches!(*op, "!" | "~" | "-" | "+" | "++" | "--") => *op,
+            _ => return self.postfix(eval),
+        };
+        self.pos += 1;
+
+        if op == "++" || op == "--" {
+            let name = match self.peek() {
+                Some(Token::Name(name)) => name.clone(),
+                _ => return Err(self.unexpected()),
+            };
+            self.pos += 1;
+            if !eval {
+                return Ok(0);
+            }
+            let value = self.apply(&op[..1], self.variable(&name)?, 1)?;
+            self.assign(&name, value)?;
+            return Ok(value);
+        }
+
+        let value = self.unary(eval)?;
+        Ok(match op {
+            "!" => (value == 0) as i64,
+            "~" => !value,
+            "-" => value
+                .checked_neg()
+                .ok_or_else(|| self.error("arithmetic overflow"))?,
+            _ => value,
+        })
+    }
+
+    /// `name++`, `name--`
+    fn postfix(&mut self, eval: bool) -> Result<i64, ExpansionError> {
+        if let (Some(Token::Name(name)), Some(Token::Operator(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+        {
+            if *op == "++" || *op == "--" {
+                let (name, op) = (name.clone(), *op);
+                self.pos += 2;
+                if !eval {
+                    return Ok(0);
+                }
+                let value = self.variable(&name)?;
+                self.assign(&name, self.apply(&op[..1], value, 1)?)?;
+                return Ok(value);
+            }
+        }
+        self.primary(eval)
+    }
+
+    /// A number, a variable or a parenthesized expression
+    fn primary(&mut self, eval: bool) -> Result<i64, ExpansionError> {
+        match self.peek().cloned() {
+            Some(Token::Number(n)) => {
+                self.pos += 1;
+                Ok(n)
+            }
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                if eval {
+                    self.variable(&name)
+                } else {
+                    Ok(0)
+                }
+            }
+            Some(Token::OpenParen) => {
+                self.pos += 1;
+                let value = self.sequence(eval)?;
+                if self.peek() != Some(&Token::CloseParen) {
+                    return Err(self.error("missing `)`"));
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// Value of the variable `name`, which may be an expression itself
+    fn variable(&self, name: &str) -> Result<i64, ExpansionError> {
+        match state::variable(name) {
+            Some(value) if !value.trim().is_empty() => evaluate_at(&value, self.depth + 1),
+            _ => Ok(0),
+        }
+    }
+
+    fn assign(&self, name: &str, value: i64) -> Result<(), ExpansionError> {
+        state::set_variable(name, value.to_string())
+            .map_err(|e| ExpansionError::Assignment(e.to_string()))
+    }
+
+    fn apply(&self, op: &str, a: i64, b: i64) -> Result<i64, ExpansionError> {
+        let overflow = || self.error("arithmetic overflow");
+        let shift = |b: i64| u32::try_from(b).ok().filter(|b| *b < i64::BITS);
+        Ok(match op {
+            "||" => (a != 0 || b != 0) as i64,
+            "&&" => (a != 0 && b != 0) as i64,
+            "|" => a | b,
+            "^" => a ^ b,
+            "&" => a & b,
+            "==" => (a == b) as i64,
+            "!=" => (a != b) as i64,
+            "<" => (a < b) as i64,
+            "<=" => (a <= b) as i64,
+            ">" => (a > b) as i64,
+            ">=" => (a >= b) as i64,
+            "<<" => a
+                .checked_shl(shift(b).ok_or_else(overflow)?)
+                .ok_or_else(overflow)?,
+            ">>" => a >> shift(b).ok_or_else(overflow)?,
+            "+" => a.checked_add(b).ok_or_else(overflow)?,
+            "-" => a.checked_sub(b).ok_or_else(overflow)?,
+            "*" => a.checked_mul(b).ok_or_else(overflow)?,
+            "/" | "%" if b == 0 => return Err(self.error("division by 0")),
+            "/" => a.checked_div(b).ok_or_else(overflow)?,
+            "%" => a.checked_rem(b).ok_or_else(overflow)?,
+            "**" if b < 0 => return Err(self.error("exponent less than 0")),
+            "**" => a
+                .checked_pow(u32::try_from(b).map_err(|_| overflow())?)
+                .ok_or_else(overflow)?,
+            _ => unreachable!("unknown arithmetic operator {}", op),
+        })
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Operator(op) => write!(f, "{}", op),
+            Token::OpenParen => write!(f, "("),
+            Token::CloseParen => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn evaluate_operators() {
+        let cases = [
+            ("1 + 2 * 3", 7),
+            ("(1 + 2) * 3", 9),
+            ("-2 ** 2", 4),
+            ("2 ** 3 ** 2", 512),
+            ("7 / 2 + 7 % 2", 4),
+            ("1 << 4 | 1 ^ 3 & 2", 19),
+            ("!0 + ~0", 0),
+            ("3 > 2 && 2 >= 2 || 0", 1),
+            ("1 == 2 ? 10 : 2 != 3 ? 20 : 30", 20),
+            ("0x1f + 010", 39),
+            ("1, 2, 3", 3),
+            ("0 && 1 / 0", 0),
+            ("  ", 0),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(evaluate(expr).unwrap(), expected, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn evaluate_variables() {
+        state::set_variable("SHRIMP_TEST_N", "5".to_owned()).unwrap();
+        state::set_variable("SHRIMP_TEST_EXPR", "SHRIMP_TEST_N * 2".to_owned()).unwrap();
+        assert_eq!(evaluate("SHRIMP_TEST_EXPR + 1").unwrap(), 11);
+        assert_eq!(evaluate("SHRIMP_TEST_N++").unwrap(), 5);
+        assert_eq!(evaluate("++SHRIMP_TEST_N").unwrap(), 7);
+        assert_eq!(evaluate("SHRIMP_TEST_N += 3").unwrap(), 10);
+        assert_eq!(evaluate("SHRIMP_TEST_N <<= 1").unwrap(), 20);
+        assert_eq!(evaluate("1 || SHRIMP_TEST_N++").unwrap(), 1);
+        assert_eq!(state::variable("SHRIMP_TEST_N"), Some("20".to_owned()));
+        state::unset_variable("SHRIMP_TEST_N").unwrap();
+        state::unset_variable("SHRIMP_TEST_EXPR").unwrap();
+        assert_eq!(evaluate("SHRIMP_TEST_N").unwrap(), 0);
+    }
+
+    #[test]
+    fn evaluate_errors() {
+        for expr in [
+            "1 / 0",
+            "1 % 0",
+            "9223372036854775807 + 1",
+            "2 ** 64",
+            "2 ** -1",
+            "1 <<",
+            "(1 + 2",
+            "1 2",
+            "08",
+            "1 @ 2",
+            "5++",
+        ] {
+            assert!(
+                matches!(evaluate(expr), Err(ExpansionError::Arithmetic(_))),
+                "{}",
+                expr
+            );
+        }
+        assert!(matches!(
+            evaluate("1 / 0"),
+            Err(ExpansionError::Arithmetic(s)) if s == "1 / 0: division by 0"
+        ));
+    }
+}
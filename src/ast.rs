@@ -0,0 +1,404 @@
+//! Typed syntax tree produced by the `parser`, following the grammar described in docs/grammar.md
+//!
+//! List → Sublist → Pipeline → Step
+//!
+//! Nodes keep the raw (non-expanded) words, expansion is only done right before a Pipeline runs,
+//! so that the effects of previous pipelines (e.g. `cd`) are visible to the next ones.
+use crate::{
+    error::ShellError,
+    input_handler::{expand_assignment_value, expand_here_doc, expand_word},
+    job,
+    pipeline::{self, Pipe, RunningPipeline},
+    redirection::Redirection,
+    state,
+    step::{self, StepOutput},
+};
+use std::{
+    fmt,
+    io::{Error, ErrorKind},
+};
+
+/// Byte range of a node in the source it was parsed from, `end` is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /// Smallest Span that covers both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// A word as typed by the user, quotes and escapes included
+#[derive(Debug, PartialEq)]
+pub struct Word {
+    pub raw: String,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Redirect {
+    pub kind: Redirection,
+    /// The delimiter, for a here-document
+    pub target: Word,
+    pub span: Span,
+    /// Only for `<<` and `<<-`
+    pub here_doc: Option<HereDoc>,
+}
+
+/// Body of a here-document, the lines between the command and the delimiter line
+#[derive(Debug, PartialEq)]
+pub struct HereDoc {
+    pub body: String,
+    /// If any part of the delimiter was quoted, the body isn't expanded
+    pub quoted: bool,
+}
+
+/// `NAME=value`, before the words of a Step
+#[derive(Debug, PartialEq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+    pub span: Span,
+}
+
+/// A Step without words, only assignments, sets shell variables. Otherwise the assignments are only passed
+/// to the environment of the Command
+#[derive(Debug, PartialEq)]
+pub struct Step {
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+    /// Only apply to this Step, in the order they were written, see docs/grammar.md
+    pub redirections: Vec<Redirect>,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Pipeline {
+    pub steps: Vec<Step>,
+    pub pipes: Vec<Pipe>,
+    pub span: Span,
+}
+
+/// `&&` and `||`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+/// Pipelines chained by `&&` / `||`
+#[derive(Debug, PartialEq)]
+pub struct Sublist {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+    pub span: Span,
+}
+
+/// How a Sublist was terminated, `;` (or a new line) and `&` respectively
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Terminator {
+    Sequential,
+    Background,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ListItem {
+    pub sublist: Sublist,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct List {
+    pub items: Vec<ListItem>,
+}
+
+impl List {
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    ///Runs every Sublist in order, returning the `StepOutput` of the last one, if any ran.
+    ///
+    /// Sublists terminated by `&` are started as a job and not waited for
+    pub fn run(self) -> Option<StepOutput> {
+        let mut last_out = None;
+        for item in self.items {
+            last_out = Some(match item.terminator {
+                Terminator::Sequential => item.sublist.execute(true),
+                Terminator::Background => {
+                    let out = item.sublist.spawn();
+                    state::set_last_status(&out);
+                    out
+                }
+            });
+        }
+        last_out
+    }
+}
+
+impl Sublist {
+    ///Runs the first Pipeline, the following ones only run if the `&&` / `||` condition is met
+    pub fn run(self) -> StepOutput {
+        self.execute(true)
+    }
+
+    fn execute(self, foreground: bool) -> StepOutput {
+        let mut last_out = self.first.execute(foreground);
+        for (connector, pipeline) in self.rest {
+            let should_run = match connector {
+                Connector::And => last_out.success,
+                Connector::Or => !last_out.success,
+            };
+            if should_run {
+                last_out = pipeline.execute(foreground);
+            }
+        }
+        last_out
+    }
+
+    ///Starts the Sublist in the background, adding it to the job table.
+    ///
+    /// A single Pipeline is a job by itself, otherwise the Sublist runs in a copy of the shell so the conditions
+    /// can be checked as each Pipeline finishes. All of its Pipelines share the process group of that copy, which
+    /// `fg`, `bg` and Ctrl-Z control as a whole
+    pub fn spawn(self) -> StepOutput {
+        if self.rest.is_empty() {
+            return self.first.spawn();
+        }
+
+        let command = self.to_string();
+        //Without job control, a background job doesn't read from the terminal
+        let foreground = job::job_control();
+        let pid = step::fork(move || self.execute(foreground).status(), None, false);
+        match pid {
+            Ok(pid) => {
+                job::background(command, RunningPipeline::forked(pid));
+            }
+            Err(e) => return Pipeline::report(Err(e.into())),
+        }
+        StepOutput::success()
+    }
+}
+
+impl Pipeline {
+    ///Expands the words of every Step and the targets of the redirections, building a Pipeline ready to run
+    pub fn expand(self) -> Result<pipeline::Pipeline, ShellError> {
+        let mut steps = Vec::with_capacity(self.steps.len());
+        for step in self.steps {
+            let mut words = vec![];
+            for word in step.words {
+                words.append(&mut expand_word(&word.raw)?);
+            }
+            let mut expanded = step::Step::new(words)?;
+            //Built-ins run inside the shell, they don't get the prefix assignments
+            if let step::Step::Command(command) = &mut expanded {
+                for assignment in &step.assignments {
+                    command.env(&assignment.name, assignment.expand()?);
+                }
+            }
+            let redirections = step
+                .redirections
+                .into_iter()
+                .map(Redirect::expand)
+                .collect::<Result<_, _>>()?;
+            steps.push((expanded, redirections));
+        }
+
+        Ok(pipeline::Pipeline::from_redirected_steps(
+            steps, self.pipes,
+        )?)
+    }
+
+    ///Expands and runs the Pipeline. Errors are reported to the user and turned into a failed `StepOutput`
+    pub fn run(self) -> StepOutput {
+        self.execute(true)
+    }
+
+    /// Checks if the Pipeline is only made of assignments, e.g. `NAME=value`
+    fn is_assignment(&self) -> bool {
+        self.steps.len() == 1 && self.steps[0].words.is_empty()
+    }
+
+    /// Sets the shell variables of an assignment-only Pipeline, see `is_assignment`
+    fn assign(self) -> Result<StepOutput, ShellError> {
+        for step in self.steps {
+            for assignment in step.assignments {
+                state::set_variable(&assignment.name, assignment.expand()?)?;
+            }
+        }
+        Ok(StepOutput::success())
+    }
+
+    /// Runs the Pipeline, giving it the terminal if in the `foreground`
+    fn execute(self, foreground: bool) -> StepOutput {
+        if self.is_assignment() {
+            let out = Pipeline::report(self.assign());
+            state::set_last_status(&out);
+            return out;
+        }
+
+        let command = self.to_string();
+        let result = self.expand().and_then(|p| {
+            let running = p.spawn(foreground)?;
+            if foreground {
+                Ok(job::wait_foreground(command, running)?)
+            } else {
+                Ok(running.finish()?)
+            }
+        });
+
+        let out = Pipeline::report(result);
+        //Background jobs don't change the shell's `$?`
+        if foreground {
+            state::set_last_status(&out);
+        }
+        out
+    }
+
+    ///Expands and starts the Pipeline as a background job
+    pub fn spawn(self) -> StepOutput {
+        //Like in a subshell, the assignments of a background job don't change the shell
+        if self.is_assignment() {
+            return StepOutput::success();
+        }
+
+        let command = self.to_string();
+        let result = self.expand().and_then(|p| {
+            let running = if p.ends_with_builtin() {
+                //The built-in would run in the shell itself, `cd /` and `exit` would change it.
+                //Like a Sublist, the job runs in a copy of the shell instead
+                let foreground = job::job_control();
+                let run = move || match p.spawn(foreground).and_then(RunningPipeline::finish) {
+                    Ok(out) => out.status(),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        1
+                    }
+                };
+                RunningPipeline::forked(step::fork(run, None, false)?)
+            } else {
+                p.spawn(false)?
+            };
+            job::background(command, running);
+            Ok(StepOutput::success())
+        });
+        Pipeline::report(result)
+    }
+
+    fn report(result: Result<StepOutput, ShellError>) -> StepOutput {
+        match result {
+            Ok(out) => out,
+            Err(e) => {
+                eprintln!("{}", e);
+                StepOutput::from(e)
+            }
+        }
+    }
+}
+
+impl Redirect {
+    /// Expands the target, or the body of a here-document, into the source or destination of the Redirection
+    fn expand(self) -> Result<(Redirection, String), ShellError> {
+        let target = match self.here_doc {
+            Some(here_doc) if here_doc.quoted => here_doc.body,
+            Some(here_doc) => expand_here_doc(&here_doc.body)?,
+            //Never split into several words, nor matched against pathnames
+            None if self.kind == Redirection::HereString => {
+                format!("{}\n", expand_assignment_value(&self.target.raw)?)
+            }
+            None => {
+                let mut target = expand_word(&self.target.raw)?;
+                if target.len() != 1 {
+                    return Err(ShellError::from(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{}: ambiguous redirect", self.target.raw),
+                    )));
+                }
+                target.pop().unwrap()
+            }
+        };
+        Ok((self.kind, target))
+    }
+}
+
+impl Assignment {
+    /// Expands the value, which is never split into several words
+    fn expand(&self) -> Result<String, ShellError> {
+        Ok(expand_assignment_value(&self.value.raw)?)
+    }
+}
+
+//Nodes are shown as the user typed them (without the original spacing), e.g. for the `jobs` built-in
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.raw)
+    }
+}
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            //`2>&1`, the target is a file descriptor
+            Redirection::DuplicateIn(_) | Redirection::DuplicateOut(_) => {
+                write!(f, "{}{}", self.kind, self.target)
+            }
+            _ => write!(f, "{} {}", self.kind, self.target),
+        }
+    }
+}
+
+impl fmt::Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let words: Vec<String> = self
+            .assignments
+            .iter()
+            .map(|a| a.to_string())
+            .chain(self.words.iter().map(|w| w.to_string()))
+            .chain(self.redirections.iter().map(|r| r.to_string()))
+            .collect();
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, step) in self.steps.iter().enumerate() {
+            if i > 0 {
+                match self.pipes.get(i - 1) {
+                    Some(Pipe::Err) => write!(f, " |& ")?,
+                    _ => write!(f, " | ")?,
+                }
+            }
+            write!(f, "{}", step)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Sublist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (connector, pipeline) in &self.rest {
+            match connector {
+                Connector::And => write!(f, " && {}", pipeline)?,
+                Connector::Or => write!(f, " || {}", pipeline)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+//! Brace expansion, the first expansion done on a word, before parameters or pathnames.
+//!
+//! * `a{b,c}d` becomes `abd` `acd`, the items can be empty or contain braces themselves: `{a,b{1,2}}`
+//! * `{1..5}`, `{5..1}`, `{a..e}` sequences, with an optional step `{1..10..3}`.
+//!   `{01..10}` pads the numbers with zeros to the same width
+//!
+//! Quoted or escaped braces, and `${...}`, are left alone, as well as braces that aren't one of the forms above,
+//! e.g. `{a}` or `{1..b}`. The quotes are kept in the words, for the following expansions to remove.
+use crate::parser;
+
+/// Words `word` expands into, just `word` if it has no valid braces
+pub fn expand(word: &str) -> Vec<String> {
+    let (start, end, items) = match find_braces(word) {
+        Some(braces) => braces,
+        None => return vec![word.to_owned()],
+    };
+
+    let prefix = &word[..start];
+    let suffixes = expand(&word[end + 1..]);
+    let mut words = vec![];
+    for item in items {
+        for item in expand(&item) {
+            for suffix in &suffixes {
+                words.push(format!("{}{}{}", prefix, item, suffix));
+            }
+        }
+    }
+    words
+}
+
+/// Finds the first valid brace expression of `word`, returning the offsets of its `{` and `}` and its items
+fn find_braces(word: &str) -> Option<(usize, usize, Vec<String>)> {
+    let mut i = 0;
+    while i < word.len() {
+        if let Some(next) = skip_quoted(word, i) {
+            i = next;
+            continue;
+        }
+        if word.as_bytes()[i] == b'{' {
+            if let Some((end, items)) = braces_at(word, i) {
+                return Some((i, end, items));
+            }
+        }
+        i += 1;
+    }
+    None
+}
+
+/// The end and items of the brace expression whose `{` is at `start`. `None` if it isn't closed, or it's neither a
+/// list nor a sequence
+fn braces_at(word: &str, start: usize) -> Option<(usize, Vec<String>)> {
+    let mut depth = 0;
+    let mut commas = vec![];
+    let mut i = start + 1;
+
+    while i < word.len() {
+        if let Some(next) = skip_quoted(word, i) {
+            i = next;
+            continue;
+        }
+        match word.as_bytes()[i] {
+            b'{' => depth += 1,
+            b'}' if depth > 0 => depth -= 1,
+            b'}' => break,
+            b',' if depth == 0 => commas.push(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    if i >= word.len() {
+        return None;
+    }
+
+    if commas.is_empty() {
+        return sequence(&word[start + 1..i]).map(|items| (i, items));
+    }
+    let mut items = vec![];
+    let mut item_start = start + 1;
+    for comma in commas {
+        items.push(word[item_start..comma].to_owned());
+        item_start = comma + 1;
+    }
+    items.push(word[item_start..i].to_owned());
+    Some((i, items))
+}
+
+/// If a quoted string, an escaped character, `${...}` or `$(...)` starts at `i`, the offset right after it.
+/// Offsets are in bytes, the special characters are all ASCII
+fn skip_quoted(word: &str, i: usize) -> Option<usize> {
+    let bytes = word.as_bytes();
+    let end = match bytes[i] {
+        b'\\' => i + 2,
+        b'\'' => word[i + 1..]
+            .find('\'')
+            .map_or(word.len(), |end| i + end + 2),
+        b'"' | b'`' => {
+            let mut j = i + 1;
+            while j < word.len() && bytes[j] != bytes[i] {
+                j += if bytes[j] == b'\\' { 2 } else { 1 };
+            }
+            j + 1
+        }
+        b'$' if bytes.get(i + 1) == Some(&b'{') => {
+            parser::closing_brace(&word[i + 2..]).map_or(word.len(), |end| i + end + 3)
+        }
+        b'$' if bytes.get(i + 1) == Some(&b'(') => {
+            parser::closing_paren(&word[i + 2..]).map_or(word.len(), |end| i + end + 3)
+        }
+        _ => return None,
+    };
+    Some(end.min(word.len()))
+}
+
+/// Items of the sequence `start..end[..step]`, of integers or of single letters
+fn sequence(content: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = content.split("..").collect();
+    let (start, end, step) = match parts[..] {
+        [start, end] => (start, end, 1),
+        [start, end, step] => (start, end, step.parse::<i64>().ok()?.checked_abs()?.max(1)),
+        _ => return None,
+    };
+
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let has_leading_zero = |n: &str| {
+            n.trim_start_matches('-').len() > 1 && n.trim_start_matches('-').starts_with('0')
+        };
+        let width = if has_leading_zero(start) || has_leading_zero(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        return Some(
+            range(first, last, step)
+                .into_iter()
+                .map(|n| format!("{:0width$}", n, width = width))
+                .collect(),
+        );
+    }
+
+    let (first, last) = match (single_letter(start), single_letter(end)) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return None,
+    };
+    Some(
+        range(first as i64, last as i64, step)
+            .into_iter()
+            .map(|c| {
+                let c = c as u8 as char;
+                //Between `Z` and `a` there are some characters the following expansions would take as special
+                if c.is_ascii_alphanumeric() {
+                    c.to_string()
+                } else {
+                    format!("\\{}", c)
+                }
+            })
+            .collect(),
+    )
+}
+
+fn single_letter(s: &str) -> Option<u8> {
+    match s.as_bytes() {
+        [c] if c.is_ascii_alphabetic() => Some(*c),
+        _ => None,
+    }
+}
+
+/// From `first` to `last` included, every `step`, going down if `last` is lower
+fn range(first: i64, last: i64, step: i64) -> Vec<i64> {
+    let step = if first <= last { step } else { -step };
+    let mut values = vec![];
+    let mut next = Some(first);
+    while let Some(n) = next.filter(|n| if step > 0 { *n <= last } else { *n >= last }) {
+        values.push(n);
+        next = n.checked_add(step);
+    }
+    values
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expand_lists() {
+        assert_eq!(
+            expand("Front-{A,B,C}-Back"),
+            vec!["Front-A-Back", "Front-B-Back", "Front-C-Back"]
+        );
+        assert_eq!(expand("{a,b{1,2}}x"), vec!["ax", "b1x", "b2x"]);
+        assert_eq!(expand("{a,b}{1,2}"), vec!["a1", "a2", "b1", "b2"]);
+        assert_eq!(expand("x{,y}"), vec!["x", "xy"]);
+        assert_eq!(expand("{a}{b,c}"), vec!["{a}b", "{a}c"]);
+        assert_eq!(expand("{a{b,c}"), vec!["{ab", "{ac"]);
+        assert_eq!(
+            expand("'{a,b}'\\{c,d} \"{e,f}\""),
+            vec!["'{a,b}'\\{c,d} \"{e,f}\""]
+        );
+        assert_eq!(expand("${A}{\"a,\",b}"), vec!["${A}\"a,\"", "${A}b"]);
+        assert_eq!(expand("{a,b"), vec!["{a,b"]);
+        assert_eq!(expand("{}"), vec!["{}"]);
+    }
+
+    #[test]
+    fn expand_sequences() {
+        assert_eq!(
+            expand("Number_{1..5}"),
+            vec!["Number_1", "Number_2", "Number_3", "Number_4", "Number_5"]
+        );
+        assert_eq!(expand("{1..10..4}"), vec!["1", "5", "9"]);
+        assert_eq!(expand("{3..-1..2}"), vec!["3", "1", "-1"]);
+        assert_eq!(expand("{08..11}"), vec!["08", "09", "10", "11"]);
+        assert_eq!(expand("{e..a..2}"), vec!["e", "c", "a"]);
+        assert_eq!(
+            expand("{Y..b}"),
+            vec!["Y", "Z", "\\[", "\\\\", "\\]", "\\^", "\\_", "\\`", "a", "b"]
+        );
+        assert_eq!(expand("{1..b}"), vec!["{1..b}"]);
+        assert_eq!(expand("{1..2..3..4}"), vec!["{1..2..3..4}"]);
+        assert_eq!(expand("{ab..c}"), vec!["{ab..c}"]);
+    }
+}
@@ -25,13 +25,42 @@ impl fmt::Debug for Builtin {
     }
 }
 
+impl fmt::Display for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every built-in, by name
+const BUILTINS: [(&str, BuiltinFn); 15] = [
+    ("cd", cd::run),
+    ("echo", echo::run),
+    ("exit", exit::run),
+    ("quit", exit::run),
+    ("jobs", jobs::run),
+    ("fg", fg::run),
+    ("bg", bg::run),
+    ("source", source::run),
+    (".", source::run),
+    ("export", export::run),
+    ("unset", unset::run),
+    ("readonly", readonly::run),
+    ("env", env::run),
+    ("set", set::run),
+    ("shopt", shopt::run),
+];
+
 impl Builtin {
     fn function_map(name: &str) -> io::Result<BuiltinFn> {
-        match name {
-            "cd" => Ok(cd::run),
-            "exit" | "quit" => Ok(exit::run),
-            _ => Err(Error::new(ErrorKind::InvalidInput, "Non-existing Built-in")),
-        }
+        BUILTINS
+            .iter()
+            .find(|(builtin, _)| *builtin == name)
+            .map(|(_, function)| *function)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Non-existing Built-in"))
     }
 
     pub fn new(name: &str) -> Builtin {
@@ -56,18 +85,211 @@ impl Builtin {
     pub fn exists(name: &str) -> bool {
         Builtin::function_map(name).is_ok()
     }
+
+    ///Names of all the builtins, e.g. for completion
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        BUILTINS.iter().map(|(name, _)| *name)
+    }
 }
 
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test {
     use super::Builtin;
     #[test]
     fn cd_root() {
         use std::{env, path::PathBuf};
 
+        let previous_dir = env::current_dir().unwrap();
         let b = Builtin::new("cd").arg("/");
 
         let _r = b.run(&[]);
-        assert_eq!(env::current_dir().unwrap(), PathBuf::from("/"))
+        let dir = env::current_dir().unwrap();
+        //Other tests rely on relative paths
+        env::set_current_dir(previous_dir).unwrap();
+        assert_eq!(dir, PathBuf::from("/"))
+    }
+
+    #[test]
+    fn source_runs_in_current_shell() {
+        use std::env;
+
+        let previous_dir = env::current_dir().unwrap();
+        let r = Builtin::new(".").arg("tests/source").run(&[]).unwrap();
+        let dir = env::current_dir().unwrap();
+        env::set_current_dir(&previous_dir).unwrap();
+
+        assert_eq!(dir, previous_dir.join("tests"));
+        //Status of the last command of the file
+        assert!(!r.success);
+        assert_eq!(r.code, Some(1));
+    }
+
+    #[test]
+    fn echo_options_and_escapes() {
+        let echo = |args: &[&str]| {
+            let b = args.iter().fold(Builtin::new("echo"), |b, arg| b.arg(arg));
+            String::from_utf8(b.run(&[]).unwrap().stdout).unwrap()
+        };
+
+        assert_eq!(echo(&["a", "b"]), "a b\n");
+        assert_eq!(echo(&[]), "\n");
+        assert_eq!(echo(&["-n", "a"]), "a");
+        assert_eq!(echo(&["-x", "a\\tb"]), "-x a\\tb\n");
+        assert_eq!(
+            echo(&["-e", "a\\tb\\\\", "\\x41\\0101\\u00e9"]),
+            "a\tb\\ AAé\n"
+        );
+        assert_eq!(echo(&["-neE", "a\\n"]), "a\\n");
+        assert_eq!(echo(&["-e", "a\\cb", "c"]), "a");
+        //Options are only read before the first word
+        assert_eq!(echo(&["a", "-n"]), "a -n\n");
+    }
+
+    #[test]
+    fn export_and_unset_variables() {
+        use crate::state;
+
+        let r = Builtin::new("export")
+            .arg("SHRIMP_TEST_EXPORT=a \"b\"")
+            .run(&[])
+            .unwrap();
+        assert!(r.success);
+        assert_eq!(state::exported_variables()["SHRIMP_TEST_EXPORT"], "a \"b\"");
+
+        let r = Builtin::new("export").run(&[]).unwrap();
+        let listed = String::from_utf8(r.stdout).unwrap();
+        assert!(listed.contains("export SHRIMP_TEST_EXPORT=\"a \\\"b\\\"\"\n"));
+
+        let r = Builtin::new("env")
+            .arg("SHRIMP_TEST_ENV=c")
+            .run(&[])
+            .unwrap();
+        let listed = String::from_utf8(r.stdout).unwrap();
+        assert!(listed.contains("SHRIMP_TEST_EXPORT=a \"b\"\n"));
+        assert!(listed.contains("SHRIMP_TEST_ENV=c\n"));
+        assert_eq!(state::variable("SHRIMP_TEST_ENV"), None);
+
+        let r = Builtin::new("export").arg("1A=b").run(&[]).unwrap();
+        assert_eq!(r.code, Some(1));
+
+        let r = Builtin::new("unset")
+            .arg("SHRIMP_TEST_EXPORT")
+            .run(&[])
+            .unwrap();
+        assert!(r.success);
+        assert_eq!(state::variable("SHRIMP_TEST_EXPORT"), None);
+
+        Builtin::new("readonly")
+            .arg("SHRIMP_TEST_BUILTIN_READONLY=1")
+            .run(&[])
+            .unwrap();
+        let r = Builtin::new("unset")
+            .arg("SHRIMP_TEST_BUILTIN_READONLY")
+            .run(&[])
+            .unwrap();
+        assert_eq!(r.code, Some(1));
+        assert_eq!(
+            String::from_utf8(r.stderr).unwrap(),
+            "unset: SHRIMP_TEST_BUILTIN_READONLY: readonly variable\n"
+        );
+    }
+
+    #[test]
+    fn set_and_shopt_options() {
+        use crate::state::{self, ShellOption};
+
+        let r = Builtin::new("set")
+            .arg("-o")
+            .arg("nullglob")
+            .run(&[])
+            .unwrap();
+        assert!(r.success);
+        assert!(state::option(ShellOption::NullGlob));
+        let r = Builtin::new("shopt").arg("nullglob").run(&[]).unwrap();
+        assert_eq!(
+            String::from_utf8(r.stdout).unwrap(),
+            "nullglob       \ton\n"
+        );
+
+        let r = Builtin::new("shopt")
+            .arg("-u")
+            .arg("nullglob")
+            .run(&[])
+            .unwrap();
+        assert!(r.success);
+        assert!(!state::option(ShellOption::NullGlob));
+        let r = Builtin::new("shopt")
+            .arg("-q")
+            .arg("nullglob")
+            .run(&[])
+            .unwrap();
+        assert!(!r.success);
+        assert!(r.stdout.is_empty());
+
+        Builtin::new("set").arg("-C").run(&[]).unwrap();
+        assert!(state::option(ShellOption::NoClobber));
+        Builtin::new("set").arg("+C").run(&[]).unwrap();
+        assert!(!state::option(ShellOption::NoClobber));
+
+        let r = Builtin::new("set").arg("+o").run(&[]).unwrap();
+        assert!(String::from_utf8(r.stdout)
+            .unwrap()
+            .contains("set +o dotglob\n"));
+
+        let r = Builtin::new("shopt")
+            .arg("-s")
+            .arg("nope")
+            .run(&[])
+            .unwrap();
+        assert_eq!(r.code, Some(1));
+        let r = Builtin::new("set").arg("-x").run(&[]).unwrap();
+        assert_eq!(r.code, Some(2));
+    }
+
+    #[test]
+    fn names_are_unique() {
+        let names: std::collections::HashSet<_> = Builtin::names().collect();
+        assert_eq!(names.len(), Builtin::names().count());
+        assert!(names.contains("cd") && names.contains("."));
+    }
+
+    #[test]
+    fn exit_status() {
+        use crate::{state, step};
+        use nix::sys::wait::{waitpid, WaitStatus};
+
+        //In a copy of the shell, as `exit` ends the process
+        let exit = |args: &'static [&'static str]| {
+            let pid = step::fork(
+                move || {
+                    let mut exit = Builtin::new("exit");
+                    for arg in args {
+                        exit = exit.arg(arg);
+                    }
+                    let _ = exit.run(&[]);
+                    0
+                },
+                None,
+                false,
+            )
+            .unwrap();
+            match waitpid(pid, None).unwrap() {
+                WaitStatus::Exited(_, code) => code,
+                status => panic!("{:?}", status),
+            }
+        };
+        assert_eq!(exit(&["3"]), 3);
+        assert_eq!(exit(&["257"]), 1);
+        assert_eq!(exit(&["-1"]), 255);
+        assert_eq!(exit(&["x"]), 2);
+        state::set_last_status(&step::StepOutput {
+            code: Some(4),
+            success: false,
+            ..step::StepOutput::success()
+        });
+        assert_eq!(exit(&[]), 4);
+        state::set_last_status(&step::StepOutput::success());
     }
 
     #[test]
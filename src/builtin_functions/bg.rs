@@ -0,0 +1,35 @@
+use crate::{job, step::StepOutput};
+
+pub fn run(args: Vec<String>, _input: &[u8]) -> StepOutput {
+    let mut code: Option<i32> = Some(0);
+    let mut stdout: Vec<u8> = vec![];
+    let mut stderr: Vec<u8> = vec![];
+
+    //Without job-ids, the current job
+    let specs: Vec<Option<&str>> = if args.is_empty() {
+        vec![None]
+    } else {
+        args.iter().map(|s| Some(s.as_str())).collect()
+    };
+
+    for spec in specs {
+        match job::find(spec).and_then(job::resume_background) {
+            Ok(line) => {
+                stdout.extend_from_slice(line.as_bytes());
+                stdout.push(b'\n');
+            }
+            Err(e) => {
+                stderr.extend_from_slice(format!("bg: {}\n", e).as_bytes());
+                code = Some(1);
+            }
+        }
+    }
+
+    StepOutput {
+        success: code == Some(0),
+        code,
+        signal: None,
+        stdout,
+        stderr,
+    }
+}
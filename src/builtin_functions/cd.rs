@@ -1,9 +1,9 @@
-use crate::step::StepOutput;
+use crate::{state, step::StepOutput};
 use std::{env, path::PathBuf};
 
 pub fn run(args: Vec<String>, _input: &[u8]) -> StepOutput {
     let mut code: Option<i32> = Some(0);
-    let mut success = false;
+    let mut success = true;
     let stdout: Vec<u8> = vec![];
     let mut stderr: Vec<u8> = vec![];
 
@@ -11,20 +11,31 @@ pub fn run(args: Vec<String>, _input: &[u8]) -> StepOutput {
     let path = if !args.is_empty() {
         PathBuf::from(&args[0])
     } else {
-        PathBuf::from(&env::var("HOME").unwrap_or_default())
+        PathBuf::from(state::variable("HOME").unwrap_or_default())
     };
 
+    let previous_dir = env::current_dir();
+
     // if malformed_path
     if env::set_current_dir(path).is_err() {
         stderr.extend_from_slice("cd: Directory not found\n".as_bytes());
         success = false;
         code = Some(1);
+    } else {
+        //For `~-` and `~+`. They can only fail if read-only
+        if let Ok(previous_dir) = previous_dir {
+            let _ = state::set_variable("OLDPWD", previous_dir.to_string_lossy().into_owned());
+        }
+        if let Ok(dir) = env::current_dir() {
+            let _ = state::set_variable("PWD", dir.to_string_lossy().into_owned());
+        }
     }
 
     // if cd failed
     StepOutput {
         success,
         code,
+        signal: None,
         stdout,
         stderr,
     }
@@ -0,0 +1,112 @@
+use crate::step::StepOutput;
+use std::{iter::Peekable, str::Chars};
+
+/// `echo [-neE] [args...]`, the arguments separated by spaces, followed by a new line.
+///
+/// * `-n` no trailing new line
+/// * `-e` interprets the escapes: `\a` `\b` `\c` (stops the output) `\e` `\f` `\n` `\r` `\t` `\v` `\\`,
+///   `\0NNN` octal, `\xHH` hexadecimal, `\uHHHH` and `\UHHHHHHHH` unicode characters
+/// * `-E` doesn't interpret them, the default
+pub fn run(args: Vec<String>, _input: &[u8]) -> StepOutput {
+    let mut new_line = true;
+    let mut escapes = false;
+
+    //Like bash, options are only recognized before the first word, and only if all their letters are valid
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next_if(|arg| is_option(arg)) {
+        for c in arg.chars().skip(1) {
+            match c {
+                'n' => new_line = false,
+                'e' => escapes = true,
+                'E' => escapes = false,
+                _ => unreachable!("other letters aren't options, see `is_option`"),
+            }
+        }
+    }
+
+    let mut stdout = String::new();
+    for (i, arg) in args.enumerate() {
+        if i > 0 {
+            stdout.push(' ');
+        }
+        if !escapes {
+            stdout.push_str(&arg);
+        } else if !unescape(&arg, &mut stdout) {
+            //`\c` produces no further output
+            new_line = false;
+            break;
+        }
+    }
+    if new_line {
+        stdout.push('\n');
+    }
+
+    StepOutput {
+        stdout: stdout.into_bytes(),
+        ..StepOutput::success()
+    }
+}
+
+fn is_option(arg: &str) -> bool {
+    arg.len() > 1 && arg.starts_with('-') && arg.chars().skip(1).all(|c| "neE".contains(c))
+}
+
+/// Pushes `arg` into `out` with its escapes interpreted. Returns false if the output stops at a `\c`
+fn unescape(arg: &str, out: &mut String) -> bool {
+    let mut chars = arg.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('a') => out.push('\x07'),
+            Some('b') => out.push('\x08'),
+            Some('c') => return false,
+            Some('e') | Some('E') => out.push('\x1b'),
+            Some('f') => out.push('\x0c'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('v') => out.push('\x0b'),
+            Some('\\') => out.push('\\'),
+            Some('0') => out.extend(char::from_u32(read_digits(&mut chars, 8, 3))),
+            Some(c @ 'x') | Some(c @ 'u') | Some(c @ 'U') => {
+                let max_digits = match c {
+                    'x' => 2,
+                    'u' => 4,
+                    _ => 8,
+                };
+                if matches!(chars.peek(), Some(d) if d.is_ascii_hexdigit()) {
+                    out.extend(char::from_u32(read_digits(&mut chars, 16, max_digits)));
+                } else {
+                    //Without any digit, the escape is kept as it is
+                    out.push('\\');
+                    out.push(c);
+                }
+            }
+            //Unknown escapes are kept as they are
+            Some(c) => {
+                out.push('\\');
+                out.push(c);
+            }
+            None => out.push('\\'),
+        }
+    }
+    true
+}
+
+/// Reads up to `max_digits` digits in `radix`, returning their value
+fn read_digits(chars: &mut Peekable<Chars>, radix: u32, max_digits: usize) -> u32 {
+    let mut value = 0;
+    for _ in 0..max_digits {
+        match chars.peek().and_then(|c| c.to_digit(radix)) {
+            Some(digit) => {
+                value = value * radix + digit;
+                chars.next();
+            }
+            None => break,
+        }
+    }
+    value
+}
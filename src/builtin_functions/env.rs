@@ -0,0 +1,23 @@
+use crate::{state, step::StepOutput};
+
+/// `env [NAME=value...]`, lists the exported variables as `NAME=value`, with the given ones added.
+///
+/// `env` followed by a command runs the external program instead, see `Step::new`
+pub fn run(args: Vec<String>, _input: &[u8]) -> StepOutput {
+    let mut variables = state::exported_variables();
+    for arg in args {
+        if let Some((name, value)) = arg.split_once('=') {
+            variables.insert(name.to_owned(), value.to_owned());
+        }
+    }
+
+    let mut stdout: Vec<u8> = vec![];
+    for (name, value) in variables {
+        stdout.extend_from_slice(format!("{}={}\n", name, value).as_bytes());
+    }
+
+    StepOutput {
+        stdout,
+        ..StepOutput::success()
+    }
+}
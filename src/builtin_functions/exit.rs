@@ -1,5 +1,17 @@
-use crate::step::StepOutput;
+use crate::{state, step::StepOutput};
 
-pub fn run(_args: Vec<String>, _input: &[u8]) -> StepOutput {
-    std::process::exit(0);
+/// `exit [n]`, exits the shell with the status `n` (its lowest 8 bits), or the one of the last command without it
+pub fn run(args: Vec<String>, _input: &[u8]) -> StepOutput {
+    let code = match args.first() {
+        None => state::last_status(),
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(n) => (n & 0xff) as i32,
+            Err(_) => {
+                //Reported right away, the shell is gone before any output could be
+                eprintln!("exit: {}: numeric argument required", arg);
+                2
+            }
+        },
+    };
+    std::process::exit(code);
 }
@@ -0,0 +1,56 @@
+use crate::{state, step::StepOutput};
+
+/// `export [NAME[=value]...]`, marks the variables to be passed to the Commands, setting their value if given.
+/// Without arguments, lists the exported variables
+pub fn run(args: Vec<String>, _input: &[u8]) -> StepOutput {
+    let mut code = Some(0);
+    let mut stdout: Vec<u8> = vec![];
+    let mut stderr: Vec<u8> = vec![];
+
+    if args.is_empty() {
+        for (name, value) in state::exported_variables() {
+            stdout.extend_from_slice(declaration("export", &name, Some(&value)).as_bytes());
+        }
+    }
+
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_owned())),
+            None => (arg.as_str(), None),
+        };
+        if !state::is_name(name) {
+            stderr.extend_from_slice(
+                format!("export: `{}`: not a valid identifier\n", arg).as_bytes(),
+            );
+            code = Some(1);
+        } else if let Err(e) = state::export_variable(name, value) {
+            stderr.extend_from_slice(format!("export: {}\n", e).as_bytes());
+            code = Some(1);
+        }
+    }
+
+    StepOutput {
+        success: code == Some(0),
+        code,
+        signal: None,
+        stdout,
+        stderr,
+    }
+}
+
+/// A line that declares the variable again when run, e.g. `export NAME="value"`
+pub fn declaration(command: &str, name: &str, value: Option<&str>) -> String {
+    match value {
+        Some(value) => {
+            let mut quoted = String::with_capacity(value.len() + 2);
+            for c in value.chars() {
+                if matches!(c, '"' | '\\' | '$' | '`') {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+            format!("{} {}=\"{}\"\n", command, name, quoted)
+        }
+        None => format!("{} {}\n", command, name),
+    }
+}
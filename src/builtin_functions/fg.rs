@@ -0,0 +1,23 @@
+use crate::{job, step::StepOutput};
+
+/// `fg [job]`, resumes the job in the foreground and waits for it. Its command line is the output of `fg`
+pub fn run(args: Vec<String>, _input: &[u8]) -> StepOutput {
+    let mut stdout: Vec<u8> = vec![];
+    let result = job::find(args.first().map(|s| s.as_str())).and_then(|id| {
+        job::foreground(id, |command| {
+            stdout.extend_from_slice(command.as_bytes());
+            stdout.push(b'\n');
+        })
+    });
+
+    match result {
+        Ok(out) => StepOutput { stdout, ..out },
+        Err(e) => StepOutput {
+            success: false,
+            code: Some(1),
+            signal: None,
+            stdout: vec![],
+            stderr: format!("fg: {}\n", e).into_bytes(),
+        },
+    }
+}
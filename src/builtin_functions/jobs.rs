@@ -0,0 +1,16 @@
+use crate::{job, step::StepOutput};
+
+pub fn run(_args: Vec<String>, _input: &[u8]) -> StepOutput {
+    let mut stdout: Vec<u8> = vec![];
+
+    //Report finished jobs first, so they aren't listed as running
+    for line in job::notify().into_iter().chain(job::list()) {
+        stdout.extend_from_slice(line.as_bytes());
+        stdout.push(b'\n');
+    }
+
+    StepOutput {
+        stdout,
+        ..StepOutput::success()
+    }
+}
@@ -1,5 +1,16 @@
+pub mod bg;
 pub mod cd;
+pub mod echo;
+pub mod env;
 pub mod exit;
+pub mod export;
+pub mod fg;
+pub mod jobs;
+pub mod readonly;
+pub mod set;
+pub mod shopt;
+pub mod source;
+pub mod unset;
 /*
 ## Built-in Commands
 
@@ -9,5 +20,13 @@ pub mod exit;
 * `fg [job-id]` sends job identified by jobid to foreground. If jobid is not specified, defaults to job which sate has been most recently modified.
 * `bg [job-id]` sends job identified by jobid to background. If jobid is not specified, defaults to job which sate has been most recently modified.
 * `jobs` output a list of currently active jobs  If a built-in command conflicts with the name of an external program, the built in command prevails --- unless the program path is explicitly given.
-* `echo`
+* `echo [-neE] [args]` writes its arguments, `-e` interpreting escapes like `\n`
+* `source <file> [args]` runs the commands of 'file' in the current shell
+* `.` same as source
+* `export [NAME[=value]...]` passes the variables to the commands, or lists the exported ones
+* `unset NAME...` removes the variables
+* `readonly [NAME[=value]...]` prevents the variables from changing, or lists the read-only ones
+* `env [NAME=value...]` lists the exported variables
+* `set [-o|+o] [option]` turns the shell options on or off, or lists them
+* `shopt [-s|-u] [-pq] [option...]` same as `set -o`, with the options of bash's `shopt`
 */
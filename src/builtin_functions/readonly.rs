@@ -0,0 +1,39 @@
+use crate::{builtin_functions::export::declaration, state, step::StepOutput};
+
+/// `readonly [NAME[=value]...]`, the variables can't be changed or unset anymore, setting their value if given.
+/// Without arguments, lists the read-only variables
+pub fn run(args: Vec<String>, _input: &[u8]) -> StepOutput {
+    let mut code = Some(0);
+    let mut stdout: Vec<u8> = vec![];
+    let mut stderr: Vec<u8> = vec![];
+
+    if args.is_empty() {
+        for (name, value) in state::readonly_variables() {
+            stdout.extend_from_slice(declaration("readonly", &name, value.as_deref()).as_bytes());
+        }
+    }
+
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_owned())),
+            None => (arg.as_str(), None),
+        };
+        if !state::is_name(name) {
+            stderr.extend_from_slice(
+                format!("readonly: `{}`: not a valid identifier\n", arg).as_bytes(),
+            );
+            code = Some(1);
+        } else if let Err(e) = state::readonly_variable(name, value) {
+            stderr.extend_from_slice(format!("readonly: {}\n", e).as_bytes());
+            code = Some(1);
+        }
+    }
+
+    StepOutput {
+        success: code == Some(0),
+        code,
+        signal: None,
+        stdout,
+        stderr,
+    }
+}
@@ -0,0 +1,75 @@
+use crate::{
+    state::{self, ShellOption},
+    step::StepOutput,
+};
+
+/// `set [-o|+o] [option]...`, turns the shell options on (`-o option`) or off (`+o option`).
+/// `-C` / `+C` are short for `-o noclobber` / `+o noclobber`
+///
+/// `set -o` alone lists the options and their state, `set +o` (or `set`) as the commands that would restore them
+pub fn run(args: Vec<String>, _input: &[u8]) -> StepOutput {
+    let mut code = Some(0);
+    let mut stdout: Vec<u8> = vec![];
+    let mut stderr: Vec<u8> = vec![];
+
+    let mut args = args.iter().peekable();
+    if args.peek().is_none() {
+        stdout.extend_from_slice(restore_commands().as_bytes());
+    }
+
+    while let Some(arg) = args.next() {
+        let on = match arg.as_str() {
+            "-o" => true,
+            "+o" => false,
+            "-C" | "+C" => {
+                state::set_option(ShellOption::NoClobber, arg == "-C");
+                continue;
+            }
+            _ => {
+                stderr.extend_from_slice(format!("set: {}: invalid option\n", arg).as_bytes());
+                code = Some(2);
+                break;
+            }
+        };
+        match args.next() {
+            //`set -o` / `set +o` alone list the options
+            None if on => {
+                for option in ShellOption::ALL {
+                    let state = if state::option(option) { "on" } else { "off" };
+                    stdout.extend_from_slice(
+                        format!("{:<15}\t{}\n", option.name(), state).as_bytes(),
+                    );
+                }
+            }
+            None => stdout.extend_from_slice(restore_commands().as_bytes()),
+            Some(name) => match ShellOption::from_name(name) {
+                Some(option) => state::set_option(option, on),
+                None => {
+                    stderr.extend_from_slice(
+                        format!("set: {}: invalid option name\n", name).as_bytes(),
+                    );
+                    code = Some(1);
+                }
+            },
+        }
+    }
+
+    StepOutput {
+        success: code == Some(0),
+        code,
+        signal: None,
+        stdout,
+        stderr,
+    }
+}
+
+/// `set -o option` or `set +o option` for every option, as they are now
+fn restore_commands() -> String {
+    ShellOption::ALL
+        .iter()
+        .map(|option| {
+            let flag = if state::option(*option) { '-' } else { '+' };
+            format!("set {}o {}\n", flag, option.name())
+        })
+        .collect()
+}
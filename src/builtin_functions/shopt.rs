@@ -0,0 +1,90 @@
+use crate::{
+    state::{self, ShellOption},
+    step::StepOutput,
+};
+
+/// `shopt [-s|-u] [-pq] [option...]`, turns the shell options on (`-s`) or off (`-u`).
+///
+/// Without `-s` or `-u` it shows the options given, or all of them, failing if any is off.
+/// `-p` shows them as `shopt` commands, `-q` shows nothing, only the status tells if they are on
+pub fn run(args: Vec<String>, _input: &[u8]) -> StepOutput {
+    let mut stdout: Vec<u8> = vec![];
+    let mut stderr: Vec<u8> = vec![];
+    let mut change = None;
+    let mut as_commands = false;
+    let mut quiet = false;
+
+    let mut args = args.into_iter().peekable();
+    while let Some(flags) = args.next_if(|arg| arg.len() > 1 && arg.starts_with('-')) {
+        for flag in flags.chars().skip(1) {
+            match flag {
+                's' => change = Some(true),
+                'u' => change = Some(false),
+                'p' => as_commands = true,
+                'q' => quiet = true,
+                _ => {
+                    return StepOutput {
+                        success: false,
+                        code: Some(2),
+                        signal: None,
+                        stdout,
+                        stderr: format!("shopt: -{}: invalid option\n", flag).into_bytes(),
+                    }
+                }
+            }
+        }
+    }
+
+    let names: Vec<String> = args.collect();
+    let mut options = vec![];
+    let mut code = Some(0);
+    for name in &names {
+        match ShellOption::from_name(name) {
+            Some(option) => options.push(option),
+            None => {
+                stderr.extend_from_slice(
+                    format!("shopt: {}: invalid shell option name\n", name).as_bytes(),
+                );
+                code = Some(1);
+            }
+        }
+    }
+
+    if let (Some(on), false) = (change, names.is_empty()) {
+        for option in options {
+            state::set_option(option, on);
+        }
+    } else {
+        //Without names, all the options, or with `-s` / `-u` those that are on / off
+        if names.is_empty() {
+            options = ShellOption::ALL
+                .iter()
+                .copied()
+                .filter(|option| change.is_none_or(|on| state::option(*option) == on))
+                .collect();
+        }
+        for option in options {
+            let on = state::option(option);
+            if !on && !names.is_empty() {
+                code = Some(1);
+            }
+            if quiet {
+                continue;
+            }
+            let line = if as_commands {
+                format!("shopt {} {}\n", if on { "-s" } else { "-u" }, option.name())
+            } else {
+                format!("{:<15}\t{}\n", option.name(), if on { "on" } else { "off" })
+            };
+            stdout.extend_from_slice(line.as_bytes());
+        }
+    }
+
+    StepOutput {
+        success: code == Some(0),
+        code,
+        signal: None,
+        stdout,
+        stderr,
+    }
+}
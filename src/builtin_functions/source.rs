@@ -0,0 +1,47 @@
+use crate::{input_handler::InputHandler, state, step::StepOutput};
+use std::{fs::File, io::BufReader};
+
+/// `source file [args...]` / `. file [args...]`, runs the commands of `file` in the current shell.
+/// While it runs, `args` (if any) replace the positional parameters
+pub fn run(args: Vec<String>, _input: &[u8]) -> StepOutput {
+    let mut args = args.into_iter();
+    let path = match args.next() {
+        Some(path) => path,
+        None => return error(2, "source: filename argument required"),
+    };
+
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => return error(1, &format!("source: {}: {}", path, e)),
+    };
+
+    let args: Vec<String> = args.collect();
+    let previous_params = if args.is_empty() {
+        None
+    } else {
+        let name = state::special_parameter('0').unwrap_or_default();
+        let previous = state::positional_parameters();
+        state::set_positional_parameters(name.clone(), args);
+        Some((name, previous))
+    };
+
+    //The output was already written by the Pipelines of the file
+    let mut out = InputHandler::from_reader(BufReader::new(file)).run_all();
+    out.stdout.clear();
+    out.stderr.clear();
+
+    if let Some((name, previous)) = previous_params {
+        state::set_positional_parameters(name, previous);
+    }
+    out
+}
+
+fn error(code: i32, message: &str) -> StepOutput {
+    StepOutput {
+        success: false,
+        code: Some(code),
+        signal: None,
+        stdout: vec![],
+        stderr: format!("{}\n", message).into_bytes(),
+    }
+}
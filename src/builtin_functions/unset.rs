@@ -0,0 +1,28 @@
+use crate::{state, step::StepOutput};
+
+/// `unset [-v] NAME...`, removes the variables, shell-local or exported
+pub fn run(args: Vec<String>, _input: &[u8]) -> StepOutput {
+    let mut code = Some(0);
+    let mut stderr: Vec<u8> = vec![];
+
+    //Only variables exist, so `-v` changes nothing
+    for name in args.iter().filter(|arg| *arg != "-v") {
+        if !state::is_name(name) {
+            stderr.extend_from_slice(
+                format!("unset: `{}`: not a valid identifier\n", name).as_bytes(),
+            );
+            code = Some(1);
+        } else if let Err(e) = state::unset_variable(name) {
+            stderr.extend_from_slice(format!("unset: {}\n", e).as_bytes());
+            code = Some(1);
+        }
+    }
+
+    StepOutput {
+        success: code == Some(0),
+        code,
+        signal: None,
+        stdout: vec![],
+        stderr,
+    }
+}
@@ -1,33 +1,107 @@
 use dirs;
-use std::{fs, path::PathBuf};
+use rustyline::config::{BellStyle, Builder, CompletionType, EditMode};
+use serde::Deserialize;
+use std::{
+    error, fmt, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/// Settings file, in the config_dir, see `Settings`
+pub const SETTINGS_FILE: &str = "config.toml";
 
 pub struct Config {
     line_editor_config: rustyline::Config,
     config_dir: PathBuf,
     history_file: PathBuf,
-    //TODO prompt, etc.
+    prompt: String,
+    secondary_prompt: String,
+}
+
+/// Invalid settings file, none of its settings are applied
+#[derive(Debug)]
+pub struct ConfigError(toml::de::Error);
+
+impl error::Error for ConfigError {}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Contents of the settings file, a TOML document with these keys. Every one of them is optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    edit_mode: Option<EditModeSetting>,
+    bell_style: Option<BellStyleSetting>,
+    completion_type: Option<CompletionTypeSetting>,
+    history_ignore_dups: Option<bool>,
+    history_ignore_space: Option<bool>,
+    auto_add_history: Option<bool>,
+    max_history_size: Option<usize>,
+    indent_size: Option<usize>,
+    history_file: Option<String>,
+    prompt: Option<String>,
+    secondary_prompt: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum EditModeSetting {
+    Emacs,
+    Vi,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum BellStyleSetting {
+    Audible,
+    Visible,
+    None,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CompletionTypeSetting {
+    List,
+    Circular,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::new()
+    }
 }
 
 impl Config {
+    /// Default Config, updated with the settings file if there's one. An invalid file is reported and ignored
     pub fn new() -> Config {
-        // TODO attempt to read
-        Config::from_default()
+        let mut config = Config::from_default();
+
+        let settings_file = config.settings_file();
+        match fs::read_to_string(&settings_file) {
+            Ok(settings) => {
+                if let Err(e) = config.apply_settings(&settings) {
+                    eprintln!("shrimp: {}: {}", settings_file.display(), e);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => eprintln!("shrimp: {}: {}", settings_file.display(), e),
+        }
+        config
     }
 
     pub fn from_default() -> Config {
-        let line_editor_config = rustyline::config::Builder::new()
-            .auto_add_history(true)
-            .indent_size(4)
-            .completion_type(rustyline::CompletionType::List)
-            .max_history_size(2048)
-            .build(); // config_path.push(["shrimp", "history"].iter().collect::<PathBuf>());
+        let line_editor_config = Config::line_editor_defaults().build();
 
         let config_dir = match dirs::config_dir() {
             Some(mut config_path) => {
                 config_path.push("shrimp");
 
                 if !config_path.exists() {
-                    fs::create_dir(&config_path);
+                    let _ = fs::create_dir(&config_path);
                 }
                 config_path
             }
@@ -36,21 +110,167 @@ impl Config {
 
         let mut history_file = config_dir.clone();
         history_file.push("shrimp_history");
-        if !history_file.exists() {
-            fs::write(&history_file, "");
-        }
+        Config::create_history_file(&history_file);
 
-        // dbg!(self.line_editor.append_history(&config_path));
         Config {
             line_editor_config,
             config_dir,
             history_file,
+            prompt: String::from(">> "),
+            secondary_prompt: String::from("> "),
         }
     }
+
+    fn line_editor_defaults() -> Builder {
+        rustyline::config::Builder::new()
+            .auto_add_history(true)
+            .indent_size(4)
+            .completion_type(rustyline::CompletionType::List)
+            .max_history_size(2048)
+    }
+
+    fn create_history_file(history_file: &Path) {
+        if !history_file.exists() {
+            let _ = fs::write(history_file, "");
+        }
+    }
+
+    /// Applies the contents of a settings file. If it's invalid the Config is left as it was
+    pub fn apply_settings(&mut self, settings: &str) -> Result<(), ConfigError> {
+        let settings: Settings = toml::from_str(settings).map_err(ConfigError)?;
+        let mut builder = Config::line_editor_defaults();
+
+        if let Some(edit_mode) = settings.edit_mode {
+            builder = builder.edit_mode(match edit_mode {
+                EditModeSetting::Emacs => EditMode::Emacs,
+                EditModeSetting::Vi => EditMode::Vi,
+            });
+        }
+        if let Some(bell_style) = settings.bell_style {
+            builder = builder.bell_style(match bell_style {
+                BellStyleSetting::Audible => BellStyle::Audible,
+                BellStyleSetting::Visible => BellStyle::Visible,
+                BellStyleSetting::None => BellStyle::None,
+            });
+        }
+        if let Some(completion_type) = settings.completion_type {
+            builder = builder.completion_type(match completion_type {
+                CompletionTypeSetting::List => CompletionType::List,
+                CompletionTypeSetting::Circular => CompletionType::Circular,
+            });
+        }
+        if let Some(ignore) = settings.history_ignore_dups {
+            builder = builder.history_ignore_dups(ignore);
+        }
+        if let Some(ignore) = settings.history_ignore_space {
+            builder = builder.history_ignore_space(ignore);
+        }
+        if let Some(auto_add) = settings.auto_add_history {
+            builder = builder.auto_add_history(auto_add);
+        }
+        if let Some(size) = settings.max_history_size {
+            builder = builder.max_history_size(size);
+        }
+        if let Some(size) = settings.indent_size {
+            builder = builder.indent_size(size);
+        }
+        self.line_editor_config = builder.build();
+
+        if let Some(history_file) = settings.history_file {
+            self.history_file = self.resolve_path(&history_file);
+            Config::create_history_file(&self.history_file);
+        }
+        if let Some(prompt) = settings.prompt {
+            self.prompt = prompt;
+        }
+        if let Some(prompt) = settings.secondary_prompt {
+            self.secondary_prompt = prompt;
+        }
+        Ok(())
+    }
+
+    /// `~/` is the home directory, relative paths are relative to the config_dir
+    fn resolve_path(&self, path: &str) -> PathBuf {
+        match (path.strip_prefix("~/"), dirs::home_dir()) {
+            (Some(rest), Some(home)) => home.join(rest),
+            _ => self.config_dir.join(path),
+        }
+    }
+
+    pub fn config_dir(&self) -> &PathBuf {
+        &self.config_dir
+    }
     pub fn line_editor_config(&self) -> &rustyline::Config {
         &self.line_editor_config
     }
     pub fn history_file(&self) -> &PathBuf {
         &self.history_file
     }
+    /// Script sourced by interactive shells at startup, unless `--norc` / `--rcfile` are given
+    pub fn rc_file(&self) -> PathBuf {
+        self.config_dir.join("shrimprc")
+    }
+    pub fn settings_file(&self) -> PathBuf {
+        self.config_dir.join(SETTINGS_FILE)
+    }
+    pub fn prompt(&self) -> &str {
+        &self.prompt
+    }
+    /// Prompt shown while a command continues in the next line
+    pub fn secondary_prompt(&self) -> &str {
+        &self.secondary_prompt
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn apply_valid_settings() {
+        let mut config = Config::from_default();
+        let result = config.apply_settings(
+            "# Comment\n\
+             edit_mode = \"vi\"\n\
+             bell_style = 'none' # No beeping\n\
+             history_ignore_dups = true\n\
+             max_history_size = 10_000\n\
+             completion_type = \"circular\"\n\
+             prompt = '\\u@\\h \\w $ '\n",
+        );
+
+        assert!(result.is_ok());
+        let editor = config.line_editor_config();
+        assert_eq!(editor.edit_mode(), EditMode::Vi);
+        assert_eq!(editor.bell_style(), BellStyle::None);
+        assert_eq!(editor.max_history_size(), 10_000);
+        assert_eq!(editor.completion_type(), CompletionType::Circular);
+        assert_eq!(config.prompt(), "\\u@\\h \\w $ ");
+        //Defaults are kept
+        assert!(editor.auto_add_history());
+        assert_eq!(config.secondary_prompt(), "> ");
+    }
+
+    #[test]
+    fn apply_invalid_settings() {
+        let mut config = Config::from_default();
+        for (settings, error) in [
+            (
+                "edit_mode = \"nano\"",
+                "unknown variant `nano`, expected `emacs` or `vi`",
+            ),
+            ("prompt = '$ '\ncolour = true", "unknown field `colour`"),
+            ("max_history_size = -1", "expected usize"),
+            ("prompt = \"unterminated", "unterminated string"),
+            ("history_ignore_dups", "expected an equals"),
+        ] {
+            let e = config.apply_settings(settings).unwrap_err().to_string();
+            assert!(e.contains(error), "{}", e);
+            assert!(e.contains("line"), "{}", e);
+        }
+        //Invalid settings keep the defaults
+        assert_eq!(config.line_editor_config().edit_mode(), EditMode::Emacs);
+        assert_eq!(config.line_editor_config().max_history_size(), 2048);
+        assert_eq!(config.prompt(), ">> ");
+    }
 }
@@ -0,0 +1,65 @@
+use crate::{input_handler::ExpansionError, parser::ParseError, step::StepOutput};
+use std::{error, fmt, fmt::Display, io};
+
+/// Any error that can happen between reading a line and finishing running it
+#[derive(Debug)]
+pub enum ShellError {
+    Io(io::Error),
+    Expansion(ExpansionError),
+    Parse(ParseError),
+}
+
+impl ShellError {
+    /// Exit code reported for the failed command, following the shell conventions
+    /// (127 for a command that wasn't found, 126 for one that couldn't be executed)
+    pub fn code(&self) -> i32 {
+        match self {
+            ShellError::Io(e) if e.kind() == io::ErrorKind::NotFound => 127,
+            ShellError::Io(e) if e.kind() == io::ErrorKind::PermissionDenied => 126,
+            ShellError::Parse(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl From<io::Error> for ShellError {
+    fn from(error: io::Error) -> Self {
+        ShellError::Io(error)
+    }
+}
+
+impl From<ExpansionError> for ShellError {
+    fn from(error: ExpansionError) -> Self {
+        ShellError::Expansion(error)
+    }
+}
+
+impl From<ParseError> for ShellError {
+    fn from(error: ParseError) -> Self {
+        ShellError::Parse(error)
+    }
+}
+
+impl From<ShellError> for StepOutput {
+    fn from(error: ShellError) -> StepOutput {
+        StepOutput {
+            success: false,
+            code: Some(error.code()),
+            signal: None,
+            stdout: vec![],
+            stderr: vec![],
+        }
+    }
+}
+
+impl error::Error for ShellError {}
+
+impl Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShellError::Io(e) => write!(f, "{}", e),
+            ShellError::Expansion(e) => write!(f, "{}", e),
+            ShellError::Parse(e) => write!(f, "{}", e),
+        }
+    }
+}
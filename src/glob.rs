@@ -0,0 +1,202 @@
+//! Pathname expansion: a word with unquoted wildcards is replaced by the sorted paths it matches, see `pattern`.
+//!
+//! * Each component of the path is matched separately, so wildcards can be in several of them: `src/*/mod.rs`
+//! * `**` as a whole component matches any number of directories, the current one included: `**/*.rs`.
+//!   Alone at the end, it matches every file and directory below
+//! * Files starting with `.` are only matched if the component starts with a literal `.`, `**` doesn't go into them,
+//!   unless the `dotglob` option is on
+//! * A trailing `/` only matches directories
+//!
+//! What happens when nothing matches depends on the `nullglob` and `failglob` options, see `input_handler`
+use crate::{
+    pattern,
+    state::{self, ShellOption},
+};
+use std::{fs, path::Path};
+
+/// Paths matching `pattern`, sorted. Quoted characters of the word must be escaped with `\`
+pub fn expand(pattern: &str) -> Vec<String> {
+    let (dir, components) = match pattern.strip_prefix('/') {
+        Some(relative) => ("/", relative.split('/').collect::<Vec<_>>()),
+        None => ("", pattern.split('/').collect()),
+    };
+
+    let mut paths = vec![];
+    walk(
+        dir,
+        &components,
+        state::option(ShellOption::DotGlob),
+        &mut paths,
+    );
+    paths.sort();
+    paths
+}
+
+/// Adds to `paths` those matching `components` inside `dir`, which is either empty (the current directory) or ends
+/// with a `/`. With `dotglob`, hidden files are like any other
+fn walk(dir: &str, components: &[&str], dotglob: bool, paths: &mut Vec<String>) {
+    let (component, rest) = match components.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    match *component {
+        //A trailing `/`, or `//`
+        "" => {
+            if rest.is_empty() {
+                if Path::new(dir).is_dir() {
+                    paths.push(dir.to_owned());
+                }
+            } else {
+                walk(&format!("{}/", dir), rest, dotglob, paths);
+            }
+        }
+        "**" => {
+            //No directory at all, unless it's the last component, then everything below is a match
+            if !rest.is_empty() {
+                walk(dir, rest, dotglob, paths);
+            }
+            for (name, is_dir) in entries(dir) {
+                if name.starts_with('.') && !dotglob {
+                    continue;
+                }
+                let path = format!("{}{}", dir, name);
+                if rest.is_empty() {
+                    paths.push(path.clone());
+                }
+                //Symbolic links aren't followed, they could make a loop
+                if is_dir {
+                    walk(&format!("{}/", path), components, dotglob, paths);
+                }
+            }
+        }
+        _ if !pattern::has_wildcards(component) => {
+            let path = format!("{}{}", dir, unescape(component));
+            if !rest.is_empty() {
+                walk(&format!("{}/", path), rest, dotglob, paths);
+            } else if Path::new(&path).symlink_metadata().is_ok() {
+                paths.push(path);
+            }
+        }
+        _ => {
+            let match_hidden =
+                dotglob || component.starts_with('.') || component.starts_with("\\.");
+            for (name, _) in entries(dir) {
+                if (name.starts_with('.') && !match_hidden) || !pattern::matches(component, &name) {
+                    continue;
+                }
+                let path = format!("{}{}", dir, name);
+                if rest.is_empty() {
+                    paths.push(path);
+                } else {
+                    walk(&format!("{}/", path), rest, dotglob, paths);
+                }
+            }
+        }
+    }
+}
+
+/// Names of the entries of `dir` and if they are directories (not following symbolic links).
+/// Unreadable directories and names that aren't valid UTF-8 are skipped
+fn entries(dir: &str) -> Vec<(String, bool)> {
+    let dir = if dir.is_empty() { "." } else { dir };
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+                Some((entry.file_name().into_string().ok()?, is_dir))
+            })
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// Removes the `\` escaping characters of a component without wildcards
+fn unescape(component: &str) -> String {
+    let mut unescaped = String::with_capacity(component.len());
+    let mut chars = component.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expand_patterns() {
+        let dir = "tests/glob";
+        fs::create_dir_all(format!("{}/sub/deep", dir)).unwrap();
+        fs::create_dir_all(format!("{}/.hidden", dir)).unwrap();
+        for file in [
+            "a1.rs",
+            "b2.rs",
+            "c.txt",
+            ".dot.rs",
+            "sub/d.rs",
+            "sub/deep/e.rs",
+            ".hidden/f.rs",
+        ] {
+            fs::write(format!("{}/{}", dir, file), "").unwrap();
+        }
+
+        let results = [
+            expand("tests/glob/*.rs"),
+            expand("tests/glob/[a-b][[:digit:]].*"),
+            expand("tests/glob/?.*"),
+            expand("tests/glob/.*.rs"),
+            expand("tests/glob/*/"),
+            expand("tests/glob/**/*.rs"),
+            expand("tests/glob/sub/**"),
+            expand("tests/*/s*/d*"),
+            expand("tests/glob/\\*.rs"),
+            expand("tests/not_a_dir/*"),
+        ];
+        state::set_option(ShellOption::DotGlob, true);
+        let dotglob = expand("tests/glob/*");
+        state::set_option(ShellOption::DotGlob, false);
+        fs::remove_dir_all(dir).unwrap();
+
+        let expected: [&[&str]; 10] = [
+            &["tests/glob/a1.rs", "tests/glob/b2.rs"],
+            &["tests/glob/a1.rs", "tests/glob/b2.rs"],
+            &["tests/glob/c.txt"],
+            &["tests/glob/.dot.rs"],
+            &["tests/glob/sub/"],
+            &[
+                "tests/glob/a1.rs",
+                "tests/glob/b2.rs",
+                "tests/glob/sub/d.rs",
+                "tests/glob/sub/deep/e.rs",
+            ],
+            &[
+                "tests/glob/sub/d.rs",
+                "tests/glob/sub/deep",
+                "tests/glob/sub/deep/e.rs",
+            ],
+            &["tests/glob/sub/d.rs", "tests/glob/sub/deep"],
+            &[],
+            &[],
+        ];
+        for (result, expected) in results.iter().zip(expected.iter()) {
+            assert_eq!(result, expected);
+        }
+        assert_eq!(
+            dotglob,
+            vec![
+                "tests/glob/.dot.rs",
+                "tests/glob/.hidden",
+                "tests/glob/a1.rs",
+                "tests/glob/b2.rs",
+                "tests/glob/c.txt",
+                "tests/glob/sub",
+            ]
+        );
+    }
+}
@@ -0,0 +1,576 @@
+//! Line editor helper of the interactive shell, see `rustyline::Helper`.
+//!
+//! Completion depends on the position of the word under the cursor:
+//!
+//! * `$NAME` or `${NAME` completes variable names
+//! * the first word of a command completes built-ins and the executables on `$PATH`, or paths if it has a `/`
+//! * any other word completes paths, respecting quotes, escapes and `~`
+//!
+//! The line is colored as it is typed (built-ins, existing and missing commands, quotes, variables and
+//! redirections), a dimmed hint suggests the rest of the last matching history entry, and incomplete input
+//! (open quotes, trailing `\\`, `|` or `&&`) makes Enter start a new line of the same command.
+use crate::{builtin::Builtin, parser, state};
+use rustyline::{
+    completion::{Completer, FilenameCompleter, Pair},
+    highlight::Highlighter,
+    hint::{Hinter, HistoryHinter},
+    validate::{ValidationContext, ValidationResult, Validator},
+    Context, Helper,
+};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    env, fs,
+    iter::Peekable,
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    str::Chars,
+};
+
+/// Characters after which a new command starts, so the next word is completed as a command name
+const COMMAND_SEPARATORS: [char; 4] = ['|', '&', ';', '('];
+
+/// Characters that end a word, other than whitespace
+const WORD_BREAKS: [char; 8] = ['|', '&', ';', '(', ')', '<', '>', '='];
+
+const BUILTIN_COLOR: &str = "\x1b[1;36m";
+const COMMAND_COLOR: &str = "\x1b[1;32m";
+const MISSING_COMMAND_COLOR: &str = "\x1b[1;31m";
+const QUOTE_COLOR: &str = "\x1b[33m";
+const VARIABLE_COLOR: &str = "\x1b[35m";
+const REDIRECTION_COLOR: &str = "\x1b[34m";
+const COMMENT_COLOR: &str = "\x1b[90m";
+const HINT_COLOR: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+pub struct ShellHelper {
+    filename_completer: FilenameCompleter,
+    history_hinter: HistoryHinter,
+    /// Whether the command names of the line being edited exist, see `command_exists`
+    known_commands: RefCell<HashMap<String, bool>>,
+}
+
+impl ShellHelper {
+    pub fn new() -> Self {
+        ShellHelper {
+            filename_completer: FilenameCompleter::new(),
+            history_hinter: HistoryHinter {},
+            known_commands: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl Default for ShellHelper {
+    fn default() -> Self {
+        ShellHelper::new()
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+
+        if let Some((start, braced)) = variable_start(line) {
+            return Ok((start, complete_variable(&line[start..], braced)));
+        }
+
+        let start = line
+            .rfind(|c: char| c.is_whitespace() || WORD_BREAKS.contains(&c))
+            .map_or(0, |i| i + 1);
+        let word = &line[start..];
+        //Paths, and quoted or escaped names, are left to the path completion
+        let path_like = word.starts_with('~') || word.contains(['/', '\'', '"', '\\']);
+        if is_command_position(&line[..start]) && !path_like {
+            return Ok((start, complete_command(word)));
+        }
+
+        self.filename_completer.complete_path(line, pos)
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<String> {
+        self.history_hinter.hint(line, pos, ctx)
+    }
+}
+
+impl Highlighter for ShellHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        Cow::Owned(highlight_line(line, &mut self.known_commands.borrow_mut()))
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{}{}{}", HINT_COLOR, hint, RESET))
+    }
+
+    //Any character can change the color of the whole word, e.g. a command that starts to exist.
+    //Looking up the commands on `$PATH` each time is avoided by `known_commands`
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        true
+    }
+}
+
+impl Validator for ShellHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        //Commands may be installed or `$PATH` changed before the next line
+        self.known_commands.borrow_mut().clear();
+        Ok(validate_input(ctx.input()))
+    }
+}
+
+impl Helper for ShellHelper {}
+
+/// If the cursor is at the end of a variable name, returns where the name starts and if it follows a `${`
+fn variable_start(line: &str) -> Option<(usize, bool)> {
+    let start = line
+        .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .map_or(0, |i| i + 1);
+    let before = &line[..start];
+
+    //Not in single quotes, nor escaped with a `\`
+    let single_quotes = before.matches('\'').count() - before.matches("\\'").count();
+    if single_quotes % 2 == 1 || before.ends_with("\\$") {
+        return None;
+    }
+
+    if before.ends_with("${") {
+        Some((start, true))
+    } else if before.ends_with('$') {
+        Some((start, false))
+    } else {
+        None
+    }
+}
+
+/// Completes the first word of a command, from the names of the built-ins and the executables on `$PATH`
+fn complete_command(prefix: &str) -> Vec<Pair> {
+    let mut names: BTreeSet<String> = Builtin::names()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| name.to_string())
+        .collect();
+
+    let path = state::variable("PATH").unwrap_or_default();
+    for dir in env::split_paths(&path) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let name = match entry.file_name().into_string() {
+                Ok(name) if name.starts_with(prefix) => name,
+                _ => continue,
+            };
+            if is_executable(&entry.path()) {
+                names.insert(name);
+            }
+        }
+    }
+
+    names
+        .into_iter()
+        .map(|name| Pair {
+            display: name.clone(),
+            replacement: name,
+        })
+        .collect()
+}
+
+/// Completes the name of a variable, shell-local or exported, closing the brace of a `${NAME}`
+fn complete_variable(prefix: &str, braced: bool) -> Vec<Pair> {
+    state::variable_names()
+        .into_iter()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| Pair {
+            replacement: if braced {
+                format!("{}}}", name)
+            } else {
+                name.clone()
+            },
+            display: name,
+        })
+        .collect()
+}
+
+/// Incomplete input keeps being edited, other parse errors are left to be reported once the line is accepted
+fn validate_input(input: &str) -> ValidationResult {
+    match parser::parse(input) {
+        Err(e) if e.is_incomplete() => ValidationResult::Incomplete,
+        _ => ValidationResult::Valid(None),
+    }
+}
+
+/// Colors the line with ANSI escapes, without changing its display width.
+///
+/// Unlike the parser, it has to accept anything the user can type, e.g. unterminated quotes.
+/// `known_commands` caches if the command names exist
+fn highlight_line(line: &str, known_commands: &mut HashMap<String, bool>) -> String {
+    let mut colored = String::with_capacity(line.len() * 2);
+    let mut chars = line.chars().peekable();
+    let mut command_position = true;
+    let mut redirection_target = false;
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' => {
+                chars.next();
+                colored.push(c);
+                command_position = true;
+            }
+            _ if c.is_whitespace() => {
+                chars.next();
+                colored.push(c);
+            }
+            '#' => {
+                colored.push_str(COMMENT_COLOR);
+                while let Some(c) = chars.next_if(|c| *c != '\n') {
+                    colored.push(c);
+                }
+                colored.push_str(RESET);
+            }
+            '&' | '|' | ';' => {
+                chars.next();
+                let mut operator = c.to_string();
+                if c == '&' && chars.peek() == Some(&'>') {
+                    redirection_target = highlight_redirection(operator, &mut chars, &mut colored);
+                    continue;
+                }
+                if c != ';' {
+                    operator.extend(chars.next_if(|next| *next == '&' || *next == c));
+                }
+                colored.push_str(&operator);
+                command_position = true;
+            }
+            '<' | '>' => {
+                redirection_target = highlight_redirection(String::new(), &mut chars, &mut colored);
+            }
+            _ => {
+                let word = read_word(&mut chars);
+                if word.chars().all(|c| c.is_ascii_digit())
+                    && matches!(chars.peek(), Some('<') | Some('>'))
+                {
+                    //File descriptor of a redirection, e.g. `2>`
+                    redirection_target = highlight_redirection(word, &mut chars, &mut colored);
+                } else if redirection_target {
+                    redirection_target = false;
+                    highlight_word(&word, &mut colored);
+                } else if command_position && is_assignment(&word) {
+                    highlight_word(&word, &mut colored);
+                } else if command_position {
+                    command_position = false;
+                    highlight_command(&word, &mut colored, known_commands);
+                } else {
+                    highlight_word(&word, &mut colored);
+                }
+            }
+        }
+    }
+    colored
+}
+
+/// Reads a word until the next unquoted metacharacter, quotes and escapes included
+fn read_word(chars: &mut Peekable<Chars>) -> String {
+    let mut word = String::new();
+    while let Some(&c) = chars.peek() {
+        match c {
+            _ if c.is_whitespace() || matches!(c, '|' | '&' | ';' | '<' | '>') => break,
+            '\'' | '"' => {
+                chars.next();
+                word.push(c);
+                while let Some(next) = chars.next() {
+                    word.push(next);
+                    if next == c {
+                        break;
+                    }
+                    if next == '\\' && c == '"' {
+                        word.extend(chars.next());
+                    }
+                }
+            }
+            '\\' => {
+                chars.next();
+                word.push(c);
+                word.extend(chars.next());
+            }
+            _ => {
+                chars.next();
+                word.push(c);
+            }
+        }
+    }
+    word
+}
+
+/// Colors a redirection operator, `operator` holds what was already read of it (`&` or a file descriptor).
+/// Returns if the next word is its target
+fn highlight_redirection(
+    mut operator: String,
+    chars: &mut Peekable<Chars>,
+    colored: &mut String,
+) -> bool {
+    while let Some(c) = chars.next_if(|c| matches!(c, '<' | '>' | '&' | '|')) {
+        operator.push(c);
+    }
+    //Duplications and closes, like `2>&1` or `>&-`
+    let duplication = operator.len() > 1 && operator.ends_with('&');
+    if duplication {
+        while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '-') {
+            operator.push(c);
+        }
+    }
+
+    colored.push_str(REDIRECTION_COLOR);
+    colored.push_str(&operator);
+    colored.push_str(RESET);
+    !duplication || operator.ends_with('&')
+}
+
+/// Colors the name of a command, depending on if it is a built-in, an existing command, or neither
+fn highlight_command(word: &str, colored: &mut String, known_commands: &mut HashMap<String, bool>) {
+    //The actual name is only known after expansion
+    if word.contains(['\'', '"', '\\', '$', '~', '*', '?']) {
+        return highlight_word(word, colored);
+    }
+
+    let color = if Builtin::exists(word) {
+        BUILTIN_COLOR
+    } else if *known_commands
+        .entry(word.to_owned())
+        .or_insert_with(|| command_exists(word))
+    {
+        COMMAND_COLOR
+    } else {
+        MISSING_COMMAND_COLOR
+    };
+    colored.push_str(color);
+    colored.push_str(word);
+    colored.push_str(RESET);
+}
+
+/// Colors the quotes and variables of a word
+fn highlight_word(word: &str, colored: &mut String) {
+    let mut chars = word.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                colored.push_str(QUOTE_COLOR);
+                colored.push(c);
+                for c in chars.by_ref() {
+                    colored.push(c);
+                    if c == '\'' {
+                        break;
+                    }
+                }
+                colored.push_str(RESET);
+            }
+            '"' => {
+                colored.push_str(QUOTE_COLOR);
+                colored.push(c);
+                while let Some(c) = chars.next() {
+                    match c {
+                        '$' => {
+                            highlight_variable(&mut chars, colored);
+                            colored.push_str(QUOTE_COLOR);
+                        }
+                        '\\' => {
+                            colored.push(c);
+                            colored.extend(chars.next());
+                        }
+                        '"' => {
+                            colored.push(c);
+                            break;
+                        }
+                        _ => colored.push(c),
+                    }
+                }
+                colored.push_str(RESET);
+            }
+            '\\' => {
+                colored.push(c);
+                colored.extend(chars.next());
+            }
+            '$' => highlight_variable(&mut chars, colored),
+            _ => colored.push(c),
+        }
+    }
+}
+
+/// Colors a variable, after its `$`: a name, a special parameter or a `${...}`
+fn highlight_variable(chars: &mut Peekable<Chars>, colored: &mut String) {
+    colored.push_str(VARIABLE_COLOR);
+    colored.push('$');
+    match chars.peek() {
+        Some('{') => {
+            for c in chars.by_ref() {
+                colored.push(c);
+                if c == '}' {
+                    break;
+                }
+            }
+        }
+        Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+            while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                colored.push(c);
+            }
+        }
+        Some('0'..='9') | Some('#') | Some('@') | Some('*') | Some('?') | Some('$') | Some('!') => {
+            colored.extend(chars.next());
+        }
+        _ => {}
+    }
+    colored.push_str(RESET);
+}
+
+/// Checks if a word is a variable assignment, e.g. `NAME=value`
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => state::is_name(name),
+        None => false,
+    }
+}
+
+/// Checks if a command can be run, from a path or looked up on `$PATH`
+fn command_exists(name: &str) -> bool {
+    if name.contains('/') {
+        return is_executable(Path::new(name));
+    }
+    let path = state::variable("PATH").unwrap_or_default();
+    env::split_paths(&path).any(|dir| !name.is_empty() && is_executable(&dir.join(name)))
+}
+
+/// Checks if `path` is a file that can be executed, following symlinks, as most of `/usr/bin` is made of them
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+/// Checks if a word preceded by `before` is the first word of a command
+fn is_command_position(before: &str) -> bool {
+    match before.trim_end().chars().last() {
+        None => true,
+        Some(c) => COMMAND_SEPARATORS.contains(&c),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rustyline::history::History;
+
+    fn complete(line: &str) -> (usize, Vec<String>) {
+        let history = History::new();
+        let (start, pairs) = ShellHelper::new()
+            .complete(line, line.len(), &Context::new(&history))
+            .unwrap();
+        (start, pairs.into_iter().map(|p| p.replacement).collect())
+    }
+
+    #[test]
+    fn complete_commands() {
+        let (start, names) = complete("ls | jo");
+        assert_eq!(start, 5);
+        assert!(names.contains(&String::from("jobs")));
+
+        let (start, names) = complete("so");
+        assert_eq!(start, 0);
+        assert!(names.contains(&String::from("source")));
+
+        let (_, names) = complete("echo a && c");
+        assert!(names.contains(&String::from("cd")));
+    }
+
+    #[test]
+    fn complete_paths() {
+        let (start, names) = complete("cat tests/sou");
+        assert_eq!(start, 4);
+        assert_eq!(names, vec!["tests/source"]);
+
+        let (start, names) = complete("cat 'tests/sou");
+        assert_eq!(start, 5);
+        assert_eq!(names, vec!["tests/source"]);
+    }
+
+    #[test]
+    fn complete_variables() {
+        state::set_variable("SHRIMP_COMPLETION_TEST", "1".to_owned()).unwrap();
+
+        let (start, names) = complete("echo $SHRIMP_COMPLETION_T");
+        assert_eq!(start, 6);
+        assert_eq!(names, vec!["SHRIMP_COMPLETION_TEST"]);
+
+        let (_, names) = complete("echo \"${SHRIMP_COMPLETION_T");
+        assert_eq!(names, vec!["SHRIMP_COMPLETION_TEST}"]);
+
+        assert_eq!(variable_start("echo '$SHRIMP"), None);
+        assert_eq!(variable_start("echo \\$SHRIMP"), None);
+    }
+
+    #[test]
+    fn highlight_commands() {
+        let mut known_commands = HashMap::new();
+        let colored = highlight_line(
+            "cd / | sh -c 'exit' && shrimp_missing_command",
+            &mut known_commands,
+        );
+        assert_eq!(
+            colored,
+            format!(
+                "{b}cd{r} / | {c}sh{r} -c {q}'exit'{r} && {m}shrimp_missing_command{r}",
+                b = BUILTIN_COLOR,
+                c = COMMAND_COLOR,
+                q = QUOTE_COLOR,
+                m = MISSING_COMMAND_COLOR,
+                r = RESET
+            )
+        );
+        //Only commands looked up on `$PATH` are remembered
+        assert_eq!(known_commands.get("sh"), Some(&true));
+        assert_eq!(known_commands.get("shrimp_missing_command"), Some(&false));
+        assert_eq!(known_commands.get("cd"), None);
+    }
+
+    #[test]
+    fn highlight_words() {
+        let colored = highlight_line("A=1 cd \"$HOME/${X}\" 2>&1 >out # cd", &mut HashMap::new());
+        assert_eq!(
+            colored,
+            format!(
+                "A=1 {b}cd{r} {q}\"{v}$HOME{r}{q}/{v}${{X}}{r}{q}\"{r} {d}2>&1{r} {d}>{r}out {c}# cd{r}",
+                b = BUILTIN_COLOR,
+                q = QUOTE_COLOR,
+                v = VARIABLE_COLOR,
+                d = REDIRECTION_COLOR,
+                c = COMMENT_COLOR,
+                r = RESET
+            )
+        );
+        //Unterminated quotes are colored up to the end
+        assert_eq!(
+            highlight_line("'a b", &mut HashMap::new()),
+            format!("{}'a b{}", QUOTE_COLOR, RESET)
+        );
+    }
+
+    #[test]
+    fn validate_incomplete_input() {
+        for input in ["echo 'a", "echo \"a", "echo a \\", "echo a |", "true &&"] {
+            assert!(matches!(
+                validate_input(input),
+                ValidationResult::Incomplete
+            ));
+        }
+        for input in ["echo 'a\nb'", "echo a |\ncat", "echo a ;;"] {
+            assert!(matches!(validate_input(input), ValidationResult::Valid(_)));
+        }
+    }
+}
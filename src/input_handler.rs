@@ -1,18 +1,31 @@
 use std::{
     env, error, fmt,
     fmt::Display,
-    fs,
-    io::{self, Write},
-    path::{Component, Path, PathBuf},
+    io::{self, BufRead, Read, Write},
+    ops::Range,
+    os::unix::io::AsRawFd,
+    path::PathBuf,
 };
 
+use nix::{errno::Errno, sys::wait::waitpid, unistd};
 use rustyline::{error::ReadlineError, Editor};
 
-use dirs;
+use crate::{
+    arithmetic,
+    ast::List,
+    brace,
+    error::ShellError,
+    glob,
+    helper::ShellHelper,
+    parser::{self, ParseError},
+    pattern, prompt,
+    state::{self, ShellOption},
+    step::{self, StepOutput},
+};
 
 #[derive(Debug)]
 pub enum InputHandlingError {
-    Expansion(ExpansionError),
+    Parse(ParseError),
     ReadLine(ReadlineError),
 }
 
@@ -20,6 +33,18 @@ pub enum InputHandlingError {
 pub enum ExpansionError {
     EnvVar(String),
     WildcardMatch(String),
+    /// `${` without its closing `}`
+    UnmatchedBrace(String),
+    /// `${...}` that isn't one of the supported forms, e.g. `${A!}`
+    BadSubstitution(String),
+    /// `${VAR:?message}` with `VAR` unset or empty
+    UnsetParameter(String),
+    /// `${VAR:=value}` where `VAR` can't be assigned, e.g. a positional parameter or a read-only variable
+    Assignment(String),
+    /// `$(...)` or `` `...` `` whose command can't be parsed, or without its closing `)` / `` ` ``
+    Substitution(String),
+    /// `$(( ))` with an invalid expression, a division by 0 or an overflow
+    Arithmetic(String),
 }
 
 impl From<io::Error> for ExpansionError {
@@ -41,112 +66,302 @@ impl Display for ExpansionError {
         match &self {
             ExpansionError::EnvVar(s) => write!(f, "Env Var error - {}", s),
             ExpansionError::WildcardMatch(s) => write!(f, "Wildcard error - {}", s),
+            ExpansionError::UnmatchedBrace(s) => write!(f, "Unmatched brace - `{}`", s),
+            ExpansionError::BadSubstitution(s) => write!(f, "Bad substitution - `{}`", s),
+            ExpansionError::UnsetParameter(s) => write!(f, "Parameter error - {}", s),
+            ExpansionError::Assignment(s) => write!(f, "Assignment error - {}", s),
+            ExpansionError::Substitution(s) => write!(f, "Command substitution error - {}", s),
+            ExpansionError::Arithmetic(s) => write!(f, "Arithmetic error - {}", s),
         }
     }
 }
 
-//TODO
-// Would be nice to have the prompt be loaded by configs
+/// Where the lines of input come from. All of them go through the same parsing and expansion
+enum InputSource {
+    /// Interactive line editor, with history
+    Editor {
+        line_editor: Box<Editor<ShellHelper>>,
+        history_file: PathBuf,
+        prompt: String,
+        secondary_prompt: String,
+    },
+    /// Script file or `-c` string
+    Reader(Box<dyn BufRead>),
+    /// Non-interactive stdin, read one byte at a time so the commands can read what comes after their line
+    Stdin,
+}
+
 pub struct InputHandler {
-    line_editor: Editor<()>,
-    history_file: PathBuf,
+    source: InputSource,
 }
 
 impl InputHandler {
+    /// Interactive input, from the line editor
     pub fn new(config: crate::Config) -> Self {
-        let mut line_editor = Editor::<()>::with_config(config.line_editor_config().clone());
+        let mut line_editor = Editor::with_config(*config.line_editor_config());
+        line_editor.set_helper(Some(ShellHelper::new()));
 
-        line_editor.load_history(config.history_file());
+        let _ = line_editor.load_history(config.history_file());
 
         let history_file = config.history_file().clone();
         InputHandler {
-            line_editor,
-            history_file,
+            source: InputSource::Editor {
+                line_editor: Box::new(line_editor),
+                history_file,
+                prompt: config.prompt().to_owned(),
+                secondary_prompt: config.secondary_prompt().to_owned(),
+            },
         }
     }
 
-    pub fn read_user_input(&mut self) -> Result<Vec<String>, InputHandlingError> {
-        let mut split_input = vec![];
+    /// Input read from a script file, a string, etc.
+    pub fn from_reader(reader: impl BufRead + 'static) -> Self {
+        InputHandler {
+            source: InputSource::Reader(Box::new(reader)),
+        }
+    }
 
-        let mut readline_result = Ok("".to_owned());
-        loop {
-            //Exception for Ctrl-C , we don't want to close the shell
-            match self.line_editor.readline(">> ") {
-                Ok(s) => {
-                    readline_result = Ok(s);
-                    break;
+    /// Input read from a stdin that isn't a terminal, e.g. `echo ls | shrimp`
+    pub fn from_stdin() -> Self {
+        InputHandler {
+            source: InputSource::Stdin,
+        }
+    }
+
+    pub fn is_interactive(&self) -> bool {
+        matches!(self.source, InputSource::Editor { .. })
+    }
+
+    /// Reads a single line, without its line terminator. The line editor shows the prompt, or the secondary prompt
+    /// for the lines that continue a command, see `prompt::render`
+    fn read_line(&mut self, continuation: bool) -> Result<String, ReadlineError> {
+        let mut line = String::new();
+        match &mut self.source {
+            InputSource::Editor {
+                line_editor,
+                prompt,
+                secondary_prompt,
+                ..
+            } => {
+                //`PS1` / `PS2` take precedence over the settings
+                let (var, template) = if continuation {
+                    ("PS2", secondary_prompt)
+                } else {
+                    ("PS1", prompt)
+                };
+                let template = state::variable(var).unwrap_or_else(|| template.clone());
+                return line_editor.readline(&prompt::render(&template));
+            }
+            InputSource::Reader(reader) => {
+                if reader.read_line(&mut line)? == 0 {
+                    return Err(ReadlineError::Eof);
                 }
-                Err(e) if !matches!(&e, ReadlineError::Interrupted) => {
-                    readline_result = Err(e);
-                    break;
+            }
+            InputSource::Stdin => {
+                let mut bytes = vec![];
+                let mut byte = [0u8];
+                loop {
+                    match unistd::read(0, &mut byte) {
+                        Ok(0) if bytes.is_empty() => return Err(ReadlineError::Eof),
+                        Ok(0) => break,
+                        Ok(_) => {
+                            bytes.push(byte[0]);
+                            if byte[0] == b'\n' {
+                                break;
+                            }
+                        }
+                        Err(Errno::EINTR) => {}
+                        Err(e) => return Err(ReadlineError::Io(e.into())),
+                    }
                 }
-                _ => (),
+                line = String::from_utf8(bytes)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
             }
         }
-        let line = readline_result.map_err(|e| InputHandlingError::ReadLine(e))?;
-        expand(&line, &mut split_input).map_err(|e| InputHandlingError::Expansion(e))?;
+
+        if line.ends_with('\n') {
+            line.pop();
+        }
+        Ok(line)
+    }
+
+    /// Reads a line from the user and parses it.
+    /// If the line is incomplete (e.g. unterminated quotes or a trailing `|`) more lines are read from the secondary prompt
+    pub fn read_user_input(&mut self) -> Result<List, InputHandlingError> {
+        let mut input = loop {
+            //Exception for Ctrl-C , we don't want to close the shell
+            match self.read_line(false) {
+                Ok(s) => break s,
+                Err(ReadlineError::Interrupted) => {}
+                Err(e) => return Err(InputHandlingError::ReadLine(e)),
+            }
+        };
+
+        let list = loop {
+            match parser::parse(&input) {
+                Err(e) if e.is_incomplete() => match self.read_line(true) {
+                    Ok(s) => {
+                        input.push('\n');
+                        input.push_str(&s);
+                    }
+                    //Ctrl-C on the secondary prompt discards the whole input
+                    Err(ReadlineError::Interrupted) => return Ok(List::default()),
+                    //The input ended in the middle of a command
+                    Err(ReadlineError::Eof) => return Err(InputHandlingError::Parse(e)),
+                    Err(e) => return Err(InputHandlingError::ReadLine(e)),
+                },
+                result => break result.map_err(InputHandlingError::Parse)?,
+            }
+        };
 
         // Save to History file
-        dbg!(self.line_editor.append_history(&self.history_file));
+        if let InputSource::Editor {
+            line_editor,
+            history_file,
+            ..
+        } = &mut self.source
+        {
+            let _ = line_editor.append_history(history_file);
+        }
 
-        Ok(split_input)
+        Ok(list)
     }
 
-    // TODO migrate functions into input_handler
-    // fn read_user_input_from_secondary_prompt(&mut self) -> String {
-    //     self.line_editor.readline(">> ").unwrap_or_default()
-    // }
+    /// Runs every command until the end of the input, returning the `StepOutput` of the last one.
+    ///
+    /// Used for scripts, where a parse error stops everything that comes after it
+    pub fn run_all(&mut self) -> StepOutput {
+        let mut last_out = StepOutput::success();
+        loop {
+            match self.read_user_input() {
+                //The status of every Pipeline is recorded as it finishes, see `state::set_last_status`
+                Ok(list) => {
+                    if let Some(out) = list.run() {
+                        last_out = out;
+                    }
+                }
+                Err(InputHandlingError::Parse(e)) => {
+                    let e = ShellError::from(e);
+                    eprintln!("{}", e);
+                    last_out = StepOutput::from(e);
+                    state::set_last_status(&last_out);
+                    return last_out;
+                }
+                Err(InputHandlingError::ReadLine(_)) => return last_out,
+            }
+        }
+    }
+}
+
+///Expands a single word, as typed by the user, into the words that will be passed to a Step.
+/// Brace expansion comes first, each of the resulting words then goes through the other expansions
+pub fn expand_word(word: &str) -> Result<Vec<String>, ExpansionError> {
+    let mut expanded = vec![];
+    for word in brace::expand(word) {
+        expand(&word, &mut expanded)?;
+    }
+    Ok(expanded)
+}
+
+///Expands the value of an assignment, `NAME=value`. It's a single word, the output of a command substitution
+/// isn't split
+pub fn expand_assignment_value(word: &str) -> Result<String, ExpansionError> {
+    let mut expanded = vec![];
+    expand_with(word, &mut expanded, false)?;
+    Ok(expanded.join(" "))
 }
 
-fn read_line_into_secondary_prompt(buf: &mut String) {
-    //PROMPT
-    print!("> ");
-    io::stdout().flush().unwrap();
-    io::stdin().read_line(buf).unwrap();
+///Expands the body of a here-document whose delimiter wasn't quoted. Like inside double quotes, except `"` is an
+/// ordinary character
+pub fn expand_here_doc(body: &str) -> Result<String, ExpansionError> {
+    let mut expanded = String::with_capacity(body.len());
+    let mut words = vec![];
+    double_quote_supression(body.to_owned(), &mut expanded, &mut words, true)?;
+    words.push(expanded);
+    Ok(words.join(" "))
 }
 
 ///Handles expansions / metacharacters the user can input on a line.
 fn expand(input_raw: &str, input_processed: &mut Vec<String>) -> Result<(), ExpansionError> {
+    expand_with(input_raw, input_processed, true)
+}
+
+/// `expand`, splitting the output of unquoted command substitutions into words only if `split`
+fn expand_with(
+    input_raw: &str,
+    input_processed: &mut Vec<String>,
+    split: bool,
+) -> Result<(), ExpansionError> {
     let mut expanded_input: Vec<String> = Vec::with_capacity(input_raw.len()); //Worst case scenario, each char is whitespace separated
     let mut curr_expansion_buffer = String::with_capacity(input_raw.len());
 
-    let mut new_line_buffer = String::new();
+    //Parts of curr_expansion_buffer that were quoted or escaped, wildcards in them match literally
+    let mut quoted: Vec<Range<usize>> = vec![];
+
     let mut leftover_buffer = String::new();
     let mut input_iter = input_raw.chars().peekable();
+    let mut prev: Option<char> = None;
 
     while let Some(c) = input_iter.next() {
+        //Expansions that split words may end the current one, its quoted parts along with it
+        let words = expanded_input.len();
         match c {
-            '$' => {
+            '$' if is_all_positional_parameters(input_iter.clone()) => {
+                if input_iter.next() == Some('{') {
+                    input_iter.nth(1);
+                }
+                expand_positional_parameters(&mut curr_expansion_buffer, &mut expanded_input);
+            }
+            '$' if input_iter.peek() == Some(&'(') => {
+                input_iter.next();
                 input_iter = set_owner_get_chars_peekable(
-                    expand_env_var(input_iter.by_ref().collect(), &mut curr_expansion_buffer)?,
+                    expand_command_substitution(
+                        input_iter.by_ref().collect(),
+                        &mut curr_expansion_buffer,
+                        if split {
+                            Some(&mut expanded_input)
+                        } else {
+                            None
+                        },
+                    )?,
                     &mut leftover_buffer,
                 );
             }
-            '*' => {
+            '`' => {
                 input_iter = set_owner_get_chars_peekable(
-                    expand_pathname_wildcard(
+                    expand_backquoted_substitution(
                         input_iter.by_ref().collect(),
                         &mut curr_expansion_buffer,
+                        if split {
+                            Some(&mut expanded_input)
+                        } else {
+                            None
+                        },
                     )?,
                     &mut leftover_buffer,
                 );
             }
-            // '?' | '[' => {
-            //     //Expand until the next non-special character
-            //     expand_pathname(input_iter.by_ref().collect(), &mut curr_expansion_buffer);
-            // }
-            '~' => {
-                if let Some(next_char) = input_iter.peek() {
-                    if *next_char == '/' || next_char.is_whitespace() {
-                        if let Some(home) = dirs::home_dir() {
-                            curr_expansion_buffer.push_str(home.to_str().unwrap_or_default());
-                        }
-                    }
-                }
-
-                //TODO else, log?
+            '$' => {
+                input_iter = set_owner_get_chars_peekable(
+                    expand_env_var(input_iter.by_ref().collect(), &mut curr_expansion_buffer)?,
+                    &mut leftover_buffer,
+                );
+            }
+            //At the start of a word, or after a `:` in an assignment, e.g. `PATH=~/bin:~/.cargo/bin`
+            '~' if prev.is_none_or(|prev| prev.is_whitespace() || (!split && prev == ':')) => {
+                let start = curr_expansion_buffer.len();
+                input_iter = set_owner_get_chars_peekable(
+                    expand_tilde(
+                        input_iter.by_ref().collect(),
+                        &mut curr_expansion_buffer,
+                        !split,
+                    ),
+                    &mut leftover_buffer,
+                );
+                quoted.push(start..curr_expansion_buffer.len());
             }
             '\'' => {
+                let start = curr_expansion_buffer.len();
                 input_iter = set_owner_get_chars_peekable(
                     single_quote_supression(
                         input_iter.by_ref().collect(),
@@ -154,48 +369,56 @@ fn expand(input_raw: &str, input_processed: &mut Vec<String>) -> Result<(), Expa
                     ),
                     &mut leftover_buffer,
                 );
+                quoted.push(start..curr_expansion_buffer.len());
             }
             '\"' => {
+                let start = curr_expansion_buffer.len();
                 input_iter = set_owner_get_chars_peekable(
                     double_quote_supression(
                         input_iter.by_ref().collect(),
                         &mut curr_expansion_buffer,
+                        &mut expanded_input,
+                        false,
                     )?,
                     &mut leftover_buffer,
                 );
+                //After a `"$@"`, the rest of the quotes is in a new word
+                if expanded_input.len() != words {
+                    quoted.clear();
+                    quoted.push(0..curr_expansion_buffer.len());
+                } else {
+                    quoted.push(start..curr_expansion_buffer.len());
+                }
             }
             '\\' => {
-                //Supresses the next character. Exception taken if it's the final character, in that case read the next line and process it.
-                match input_iter.peek() {
-                    Some(_c) => {
-                        curr_expansion_buffer.push(input_iter.next().unwrap());
-                    }
-                    None => {
-                        new_line_buffer.clear();
-                        read_line_into_secondary_prompt(&mut new_line_buffer);
-                        input_iter = new_line_buffer.chars().peekable();
-                    }
+                //Supresses the next character. A trailing `\` is a line continuation, already removed by the parser
+                if let Some(c) = input_iter.next() {
+                    let start = curr_expansion_buffer.len();
+                    curr_expansion_buffer.push(c);
+                    quoted.push(start..curr_expansion_buffer.len());
                 }
             }
             _ if c.is_whitespace() => {
-                //We don't want to **clone** a String with the same capacity as curr_expansion_buffer
-                // .as_str().to_string() will allocate only the needed space,
-                // while ownership of curr_expansion_buffer remains in this function
-                expanded_input.push(curr_expansion_buffer.as_str().to_string());
+                push_word(&mut expanded_input, &curr_expansion_buffer, &quoted, split)?;
                 curr_expansion_buffer.clear();
+                quoted.clear();
             }
             _ => {
                 curr_expansion_buffer.push(c);
             }
         }
+
+        if expanded_input.len() != words && c != '"' {
+            quoted.clear();
+        }
+        prev = Some(c);
     }
 
     //sanity checking to avoid adding empty String to split input
     if !curr_expansion_buffer.is_empty() {
-        expanded_input.push(curr_expansion_buffer.as_str().to_string());
+        push_word(&mut expanded_input, &curr_expansion_buffer, &quoted, split)?;
     }
 
-    dbg!(&expanded_input);
     input_processed.append(&mut expanded_input);
     Ok(())
 }
@@ -205,315 +428,656 @@ fn expand(input_raw: &str, input_processed: &mut Vec<String>) -> Result<(), Expa
     and a mutable reference to processed curr_expanded_buffer as args.
 The function then performs it's expansion, pushing the new characters to curr_expanded_buffer. It then returns all remaining characters.
 */
-
-/// Replaces the first string composed of alphanumeric and `_` with the value of a environment variable of the same name, or blank "" as a default
+/// Replaces the first string composed of alphanumeric and `_` with the value of a variable of the same name, shell-local or exported, or blank "" as a default.
+/// Special parameters (`$?`, `$$`, `$!`) are a single character, see `state::special_parameter`.
+/// `${...}` is handled by `expand_braced_parameter`
 /// Returns any leftover input
 fn expand_env_var(
     input_buffer: String,
     curr_expanded_buffer: &mut String,
-) -> Result<String, env::VarError> {
+) -> Result<String, ExpansionError> {
+    if let Some(braced) = input_buffer.strip_prefix('{') {
+        return expand_braced_parameter(braced, curr_expanded_buffer);
+    }
+
+    if let Some(value) = input_buffer
+        .chars()
+        .next()
+        .and_then(state::special_parameter)
+    {
+        curr_expanded_buffer.push_str(&value);
+        return Ok(input_buffer[1..].to_owned());
+    }
+
     //Get var name
     //Get up until a delimiter... i.e. read alphanumeric and _
     let (var_name, _) = input_buffer
         .split_once(|c| !char::is_alphanumeric(c) && c != '_')
-        .unwrap_or((&input_buffer, &""));
+        .unwrap_or((&input_buffer, ""));
     // .unwrap_or_default();
 
-    dbg!(&var_name);
-    let value = env::var(var_name)?;
+    //A lone `$` is kept as is
+    if var_name.is_empty() {
+        curr_expanded_buffer.push('$');
+    }
 
-    curr_expanded_buffer.push_str(&value);
+    if let Some(value) = state::variable(var_name) {
+        curr_expanded_buffer.push_str(&value);
+    }
 
-    Ok(input_buffer
-        .strip_suffix(var_name)
-        .unwrap_or_default()
-        .to_owned())
+    Ok(input_buffer[var_name.len()..].to_owned())
 }
 
-fn expand_pathname_wildcard(
-    input_buffer: String,
+/// `${...}`, `input_buffer` starting right after the `{`:
+/// * `${NAME}` the value, `${#NAME}` its length
+/// * `${NAME:-word}` word if unset or empty, `${NAME:=word}` also assigns it, `${NAME:?message}` fails with the message,
+///   `${NAME:+word}` word only if set and not empty. Without `:` only unset counts
+/// * `${NAME#pattern}` / `${NAME##pattern}` removes the shortest / longest prefix matching the pattern,
+///   `${NAME%pattern}` / `${NAME%%pattern}` the suffix, see `pattern`
+/// * `${NAME:offset}` / `${NAME:offset:length}` substring, negative values count from the end
+/// * `${NAME/pattern/string}` replaces the first match, `//` every match, `/#` a prefix and `/%` a suffix
+///
+/// Returns any leftover input
+fn expand_braced_parameter(
+    input_buffer: &str,
     curr_expanded_buffer: &mut String,
 ) -> Result<String, ExpansionError> {
-    // base_dir/{prefix}*[{intermediate}*...]{suffix}[/{child_path}]
-
-    // (base_dir) We need the most complete path possible (i.e. the nearest dir) up until '*' (remember it can appear in the middle), defaulting to CWD
-    // (prefix) The remainder between base_dir and the first '*', if any.
-    // (intermediates) Should there be several '*' in the level, it's the text between two of them
-    // (suffix) Everything in the file name after '*' , e.g. extensions, etc.
-
-    //There can be multiple '*' in the same level of the fs hierarchy we're checking.
-    // This function will handle this
-
-    // We can have multiple, separate '*' - my/dir/*/sub_dir/* -
-    // This function will only handle wildcards ONE level of the fs hierarchy.
-    // It will enumerate existing matches and re-add them to the input_buffer for the main loop to re-process
-
-    // Auxiliar function used to select which entries are a match
-
-    fn is_wildcard_match(
-        entry: &str,
-        prefix: &str,
-        intermediates: &Vec<String>,
-        suffix: &str,
-    ) -> bool {
-        return entry.starts_with(&prefix)
-            && entry.ends_with(&suffix)
-            && intermediates.iter().fold(true, |acc, intermediary| {
-                acc && entry.contains(intermediary)
-            });
+    let end = parser::closing_brace(input_buffer)
+        .ok_or_else(|| ExpansionError::UnmatchedBrace(format!("${{{}", input_buffer)))?;
+    let content = &input_buffer[..end];
+    let bad_substitution = || ExpansionError::BadSubstitution(format!("${{{}}}", content));
+
+    //`${#NAME}`, not to be confused with `${#}` or `${#-word}`
+    if let Some(name) = content.strip_prefix('#') {
+        if !name.is_empty() && parameter_name_len(name) == Some(name.len()) {
+            let length = match name {
+                "@" | "*" => state::positional_parameters().len(),
+                _ => parameter(name).unwrap_or_default().chars().count(),
+            };
+            curr_expanded_buffer.push_str(&length.to_string());
+            return Ok(input_buffer[end + 1..].to_owned());
+        }
     }
 
-    #[derive(Debug)]
-    struct WildcardComponents {
-        base_dir_path: PathBuf,
-        prefix: Option<String>,
-        wildcard_intermediates: Vec<String>,
-        wildcard_suffix: Option<String>,
-        child_path: PathBuf,
-    }
-    fn get_wildcard_components(
-        curr_expanded_buffer: &str,
-        input_buffer: String,
-    ) -> Result<(WildcardComponents, String), ExpansionError> {
-        let base_dir_and_prefix = PathBuf::from(&curr_expanded_buffer);
-
-        let mut base_dir_path = PathBuf::new();
-        let mut prefix: Option<String> = None;
-        let mut wildcard_intermediates: Vec<String> = vec![];
-        let mut wildcard_suffix: Option<String> = None;
-
-        dbg!(&base_dir_and_prefix);
-        // Check if path exists. If not, Check if up until the parent it exists, default to PWD
-        if base_dir_and_prefix.exists() {
-            base_dir_path = base_dir_and_prefix
-        }
-        // Non-existing base_dir
-        // We have to check the String because PathBuf normalizes trailing slashes
-        else if curr_expanded_buffer.ends_with(std::path::MAIN_SEPARATOR) {
-            return Err(ExpansionError::WildcardMatch(format!(
-                "Non Existing directory {}",
-                base_dir_and_prefix.as_os_str().to_str().unwrap_or_default()
+    let (name, operation) =
+        content.split_at(parameter_name_len(content).ok_or_else(bad_substitution)?);
+    let value = parameter(name);
+
+    //With a `:`, an empty value is the same as an unset one
+    let (colon, operation) = match operation.strip_prefix(':') {
+        Some(rest) if rest.starts_with(['-', '=', '?', '+']) => (true, rest),
+        _ => (false, operation),
+    };
+    let set = matches!(&value, Some(value) if !(colon && value.is_empty()));
+    let value_or_empty = value.clone().unwrap_or_default();
+
+    let mut chars = operation.chars();
+    let expanded = match chars.next() {
+        None => value_or_empty,
+        Some('-') if set => value_or_empty,
+        Some('-') => expand_operand(chars.as_str())?,
+        Some('=') if set => value_or_empty,
+        Some('=') => {
+            if !state::is_name(name) {
+                return Err(ExpansionError::Assignment(format!(
+                    "${}: cannot assign in this way",
+                    name
+                )));
+            }
+            let word = expand_operand(chars.as_str())?;
+            state::set_variable(name, word.clone())
+                .map_err(|e| ExpansionError::Assignment(e.to_string()))?;
+            word
+        }
+        Some('?') if set => value_or_empty,
+        Some('?') => {
+            let message = match expand_operand(chars.as_str())? {
+                message if message.is_empty() => "parameter null or not set".to_owned(),
+                message => message,
+            };
+            return Err(ExpansionError::UnsetParameter(format!(
+                "{}: {}",
+                name, message
             )));
-        } else {
-            base_dir_path = if base_dir_and_prefix
-                .parent()
-                .unwrap_or(&Path::new(""))
-                .is_dir()
-            {
-                base_dir_and_prefix.parent().unwrap().to_path_buf()
+        }
+        Some('+') if set => expand_operand(chars.as_str())?,
+        Some('+') => String::new(),
+        Some(':') => substring(&value_or_empty, chars.as_str())?.ok_or_else(bad_substitution)?,
+        Some(c @ '#') | Some(c @ '%') => {
+            let rest = chars.as_str();
+            let (longest, rest) = match rest.strip_prefix(c) {
+                Some(rest) => (true, rest),
+                None => (false, rest),
+            };
+            let pattern = expand_pattern(rest)?;
+            let matched = if c == '#' {
+                pattern::match_prefix(&pattern, &value_or_empty, longest)
             } else {
-                PathBuf::from("./")
-                // std::env::current_dir()?
+                pattern::match_suffix(&pattern, &value_or_empty, longest)
             };
+            match matched {
+                //Ranges are in characters
+                Some(range) => value_or_empty
+                    .chars()
+                    .enumerate()
+                    .filter(|(i, _)| !range.contains(i))
+                    .map(|(_, c)| c)
+                    .collect(),
+                None => value_or_empty,
+            }
+        }
+        Some('/') => {
+            let rest = chars.as_str();
+            let (mode, rest) = match rest.chars().next() {
+                Some(mode @ '/') | Some(mode @ '#') | Some(mode @ '%') => (mode, &rest[1..]),
+                _ => ('/', rest),
+            };
+            let all = mode == '/' && operation.starts_with("//");
+            //The pattern ends at the first unescaped `/`
+            let mut escaped = false;
+            let separator = rest.find(|c| {
+                let found = c == '/' && !escaped;
+                escaped = c == '\\' && !escaped;
+                found
+            });
+            let (pattern, replacement) = match separator {
+                Some(i) => (&rest[..i], &rest[i + 1..]),
+                None => (rest, ""),
+            };
+            replace(
+                &value_or_empty,
+                &expand_pattern(pattern)?,
+                &expand_operand(replacement)?,
+                mode,
+                all,
+            )
+        }
+        _ => return Err(bad_substitution()),
+    };
 
-            prefix = base_dir_and_prefix
-                .file_name()
-                .unwrap_or_default()
-                .to_str()
-                .map(String::from)
-        };
-
-        let (remaining_path, input_buffer) = input_buffer
-            .split_once(|c: char| c.is_whitespace())
-            .unwrap_or((&input_buffer, "")); //if no whitespace is present, the rest of input_buffer has to be processed
+    curr_expanded_buffer.push_str(&expanded);
+    Ok(input_buffer[end + 1..].to_owned())
+}
 
-        // Includes intermediates , other '*' , the suffix and child path
-        let remaining_path = PathBuf::from(remaining_path);
-        let mut remaining_path_iter = remaining_path.components();
+/// Length of the parameter name at the start of `s`: a variable name, a number or a special parameter
+fn parameter_name_len(s: &str) -> Option<usize> {
+    let first = s.chars().next()?;
+    let len = if first.is_ascii_alphabetic() || first == '_' {
+        s.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(s.len())
+    } else if first.is_ascii_digit() {
+        s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len())
+    } else if matches!(first, '@' | '*' | '#' | '?' | '$' | '!') {
+        1
+    } else {
+        return None;
+    };
+    Some(len)
+}
 
-        // Get Intermediates
-        if let Some(Component::Normal(first_component)) = remaining_path_iter.next() {
-            let mut intermediates_and_suffix = first_component
-                .to_str()
-                .unwrap_or_default()
-                .split("*")
-                .collect::<Vec<&str>>();
+/// Value of a parameter inside `${...}`, `None` if unset
+fn parameter(name: &str) -> Option<String> {
+    match name {
+        "@" | "*" => {
+            let params = state::positional_parameters();
+            Some(params.join(" ")).filter(|_| !params.is_empty())
+        }
+        "!" => state::special_parameter('!').filter(|pid| !pid.is_empty()),
+        _ if name.starts_with(|c: char| c.is_ascii_digit()) => {
+            state::positional_parameter(name.parse().ok()?)
+        }
+        _ if !state::is_name(name) => state::special_parameter(name.chars().next()?),
+        _ => state::variable(name),
+    }
+}
 
-            wildcard_suffix = intermediates_and_suffix.pop().map(String::from);
+/// Expands the word of a form like `${NAME:-word}`, it is never split into several words
+fn expand_operand(word: &str) -> Result<String, ExpansionError> {
+    Ok(expand_word(word)?.join(" "))
+}
 
-            wildcard_intermediates = intermediates_and_suffix
-                .into_iter()
-                .map(|e| e.to_owned())
-                .collect();
+/// Expands the pattern of a removal or replacement form. Pathnames aren't expanded, and quoted characters are
+/// escaped so they match literally
+fn expand_pattern(word: &str) -> Result<String, ExpansionError> {
+    fn push_literal(pattern: &mut String, c: char) {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            pattern.push('\\');
         }
+        pattern.push(c);
+    }
 
-        let child_path: PathBuf = remaining_path_iter.collect();
-
-        Ok((
-            WildcardComponents {
-                base_dir_path,
-                prefix,
-                wildcard_intermediates,
-                wildcard_suffix,
-                child_path,
-            },
-            input_buffer.to_owned(),
-        ))
-    }
-
-    dbg!(&curr_expanded_buffer);
-    dbg!(&input_buffer);
-
-    let (wildcard_components, input_buffer) =
-        get_wildcard_components(&curr_expanded_buffer, input_buffer)?;
-
-    dbg!(&wildcard_components);
-    let mut entries = fs::read_dir(&wildcard_components.base_dir_path)?
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .map(|e| e.path())
-        .filter(|e| {
-            is_wildcard_match(
-                &e.file_name()
-                    .unwrap_or_default()
-                    .to_str()
-                    .unwrap_or_default(),
-                wildcard_components
-                    .prefix
-                    .as_ref()
-                    .unwrap_or(&String::new()),
-                &wildcard_components.wildcard_intermediates,
-                wildcard_components
-                    .wildcard_suffix
-                    .as_ref()
-                    .unwrap_or(&String::new()),
-            )
-        })
-        .collect::<Vec<PathBuf>>();
-
-    //BUG if nothing is found, we should return an error
-    // Not exactly a bug, but we're failing silently
-    //Possibly - instead of adding to input_buffer, we iterate until we run out ?
-    // if entries.is_empty() {
-    //     curr_expanded_buffer.clear();
-    //     return Err(ExpansionError::WildcardMatch("No match found".to_owned()));
-    // }
-
-    //If has child path, ignore all files
-    if wildcard_components.child_path.capacity() != 0 {
-        entries = entries
-            .into_iter()
-            .filter(|e| e.is_dir())
-            .collect::<Vec<_>>();
-
-        entries
-            .iter_mut()
-            .for_each(|e| e.push(&wildcard_components.child_path));
-    }
-
-    let entries = entries
-        .iter()
-        .filter_map(|e| e.to_str())
-        .map(|e| {
-            let mut e_owned = String::from(e);
-            //remove "./" from the beginning if user didn't explicitly added it
-            if e.starts_with("./") && !curr_expanded_buffer.starts_with("./") {
-                e_owned = e.trim_start_matches("./").to_owned();
+    let mut pattern = String::with_capacity(word.len());
+    let mut leftover_buffer = String::new();
+    let mut chars = word.chars();
+    let mut quote: Option<char> = None;
+
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            (q, None) if q == '\'' || q == '"' => quote = Some(q),
+            (q, Some(open)) if q == open => quote = None,
+            (c, Some('\'')) => push_literal(&mut pattern, c),
+            ('\\', _) => {
+                if let Some(next) = chars.next() {
+                    push_literal(&mut pattern, next);
+                }
             }
-            //If the entries have whitespaces in them, they must be quoted before appending
-            if e.contains(|c: char| c.is_whitespace()) {
-                e_owned = format!("'{}'", e)
+            ('$', _) => {
+                let mut value = String::new();
+                chars = set_owner_get_chars(
+                    expand_env_var(chars.by_ref().collect(), &mut value)?,
+                    &mut leftover_buffer,
+                );
+                //The value of an unquoted variable is a pattern itself
+                if quote.is_some() {
+                    value.chars().for_each(|c| push_literal(&mut pattern, c));
+                } else {
+                    pattern.push_str(&value);
+                }
             }
-            e_owned
-        })
-        .collect::<Vec<String>>();
+            (c, Some(_)) => push_literal(&mut pattern, c),
+            (c, None) => pattern.push(c),
+        }
+    }
+    Ok(pattern)
+}
 
-    //Clear curr_expanded buffer so Main loop has a fresh start
-    curr_expanded_buffer.clear();
+/// `${NAME:offset:length}`, `spec` being what comes after the first `:`. `None` if the numbers aren't valid
+fn substring(value: &str, spec: &str) -> Result<Option<String>, ExpansionError> {
+    let (offset, length) = match spec.split_once(':') {
+        Some((offset, length)) => (offset, Some(length)),
+        None => (spec, None),
+    };
+    let chars: Vec<char> = value.chars().collect();
+    let len = chars.len() as i64;
 
-    let joined_entries = if entries.is_empty() {
-        input_buffer.to_string()
+    let offset: i64 = match expand_operand(offset)?.trim().parse() {
+        Ok(offset) => offset,
+        Err(_) => return Ok(None),
+    };
+    let start = if offset < 0 {
+        len + offset
     } else {
-        let joined_entries = entries.join(" ");
+        offset.min(len)
+    };
+    //Counting from the end, past the start of the value
+    if start < 0 {
+        return Ok(Some(String::new()));
+    }
+
+    let end = match length {
+        None => len,
+        Some(length) => match expand_operand(length)?.trim().parse::<i64>() {
+            Ok(length) if length < 0 => len + length,
+            Ok(length) => (start + length).min(len),
+            Err(_) => return Ok(None),
+        },
+    };
+    if end < start {
+        return Ok(None);
+    }
+    Ok(Some(chars[start as usize..end as usize].iter().collect()))
+}
+
+/// `${NAME/pattern/replacement}`, with `mode` `#` or `%` the match must be a prefix or a suffix.
+/// Replaces every match if `all`, otherwise just the first one
+fn replace(value: &str, pattern: &str, replacement: &str, mode: char, all: bool) -> String {
+    if pattern.is_empty() {
+        return value.to_owned();
+    }
+
+    let chars: Vec<char> = value.chars().collect();
+    let mut found = match mode {
+        '#' => pattern::match_prefix(pattern, value, true),
+        '%' => pattern::match_suffix(pattern, value, true),
+        _ => pattern::find(pattern, value, 0),
+    };
 
-        if !input_buffer.is_empty() {
-            format!("{} {}", joined_entries, input_buffer)
+    let mut replaced = String::with_capacity(value.len());
+    let mut done = 0;
+    while let Some(range) = found {
+        replaced.extend(&chars[done..range.start]);
+        replaced.push_str(replacement);
+        done = range.end;
+        found = if all && range.end < chars.len() {
+            pattern::find(pattern, value, range.end)
         } else {
-            joined_entries
+            None
+        };
+    }
+    replaced.extend(&chars[done..]);
+    replaced
+}
+
+/// `$(...)`, `input_buffer` starting right after the `(`. The command inside is parsed and run, its output
+/// replacing the substitution. Outside double quotes, i.e. when there are `words` to add to, the output is split on
+/// whitespace. `$(( ))` is handled by `expand_arithmetic`.
+/// Returns any leftover input
+fn expand_command_substitution(
+    input_buffer: String,
+    curr_expanded_buffer: &mut String,
+    words: Option<&mut Vec<String>>,
+) -> Result<String, ExpansionError> {
+    if let Some(inner) = input_buffer.strip_prefix('(') {
+        //Only if the inner `(` is closed right before the outer one, otherwise it's a subshell in a substitution
+        if let Some(end) =
+            parser::closing_paren(inner).filter(|end| inner[end + 1..].starts_with(')'))
+        {
+            return expand_arithmetic(inner, end, curr_expanded_buffer);
+        }
+    }
+
+    let end = parser::closing_paren(&input_buffer).ok_or_else(|| {
+        ExpansionError::Substitution(format!("unterminated `$({}`", input_buffer))
+    })?;
+    let output = command_substitution(&input_buffer[..end])?;
+    push_substitution_output(&output, curr_expanded_buffer, words);
+    Ok(input_buffer[end + 1..].to_owned())
+}
+
+/// `$(( expr ))`, `input_buffer` starting right after the `$((`, with its closing `))` at `end`.
+/// The expression is expanded like inside double quotes, e.g. `$(( $A + 1 ))`, and then evaluated by `arithmetic`.
+/// Returns any leftover input
+fn expand_arithmetic(
+    input_buffer: &str,
+    end: usize,
+    curr_expanded_buffer: &mut String,
+) -> Result<String, ExpansionError> {
+    let mut expr = String::new();
+    let mut words = vec![];
+    //Quotes inside the expression are simply removed
+    let mut rest = input_buffer[..end].to_owned();
+    while !rest.is_empty() {
+        rest = double_quote_supression(rest, &mut expr, &mut words, false)?;
+    }
+    words.push(expr);
+
+    let value = arithmetic::evaluate(&words.join(" "))?;
+    curr_expanded_buffer.push_str(&value.to_string());
+    Ok(input_buffer[end + 2..].to_owned())
+}
+
+/// `` `...` ``, the old form of `$(...)`. `input_buffer` starts right after the opening backquote.
+/// Inside, `\`, `` \` `` and `\$` stand for the character itself. Returns any leftover input
+fn expand_backquoted_substitution(
+    input_buffer: String,
+    curr_expanded_buffer: &mut String,
+    words: Option<&mut Vec<String>>,
+) -> Result<String, ExpansionError> {
+    let mut source = String::new();
+    let mut input_iter = input_buffer.chars();
+    let mut closed = false;
+
+    while let Some(c) = input_iter.next() {
+        match c {
+            '\\' => match input_iter.clone().next() {
+                Some(next @ ('\\' | '`' | '$')) => {
+                    input_iter.next();
+                    source.push(next);
+                }
+                _ => source.push(c),
+            },
+            '`' => {
+                closed = true;
+                break;
+            }
+            _ => source.push(c),
         }
+    }
+    if !closed {
+        return Err(ExpansionError::Substitution(format!(
+            "unterminated `` `{} ``",
+            input_buffer
+        )));
+    }
+
+    let output = command_substitution(&source)?;
+    push_substitution_output(&output, curr_expanded_buffer, words);
+    Ok(input_iter.collect())
+}
+
+/// Parses and runs `source` in a subshell, returning what it wrote to stdout without the trailing new lines.
+/// The subshell is a copy of the shell: `cd`, variables, options and `exit` inside of it don't change the shell,
+/// e.g. `$(cd dir; pwd)`. Its exit status becomes `$?`
+fn command_substitution(source: &str) -> Result<String, ExpansionError> {
+    let list = parser::parse(source).map_err(|e| ExpansionError::Substitution(e.to_string()))?;
+    let (mut reader, writer) = io::pipe()?;
+    let run = move || {
+        if unistd::dup2(writer.as_raw_fd(), 1).is_err() {
+            return 1;
+        }
+        drop(writer);
+        list.run();
+        let _ = io::stdout().flush();
+        state::last_status()
     };
+    //It stays in the process group of the shell, which has the terminal while the line is expanded
+    let pid = step::fork(run, Some(unistd::getpgrp()), false)?;
+
+    let mut output = vec![];
+    let read = reader.read_to_end(&mut output);
+    let status = waitpid(pid, None).map_err(io::Error::from)?;
+    read?;
+    state::set_last_status(&StepOutput::from(status));
+
+    let mut stdout = String::from_utf8_lossy(&output).into_owned();
+    stdout.truncate(stdout.trim_end_matches('\n').len());
+    Ok(stdout)
+}
 
-    dbg!(&joined_entries);
-    Ok(joined_entries)
+/// Adds the output of a command substitution to the current word. With `words`, whitespace in it ends the word,
+/// like unquoted whitespace typed by the user
+fn push_substitution_output(
+    output: &str,
+    curr_expanded_buffer: &mut String,
+    words: Option<&mut Vec<String>>,
+) {
+    let words = match words {
+        Some(words) => words,
+        None => return curr_expanded_buffer.push_str(output),
+    };
+    for c in output.chars() {
+        if !c.is_whitespace() {
+            curr_expanded_buffer.push(c);
+        } else if !curr_expanded_buffer.is_empty() {
+            words.push(curr_expanded_buffer.as_str().to_string());
+            curr_expanded_buffer.clear();
+        }
+    }
+}
+
+/// `~name`, `input_buffer` starting right after the `~`, up to the next `/` (or `:` in an `assignment`) or the end.
+/// It becomes the home directory of the user `name`, or of the current user without one. `~+` is the current directory
+/// and `~-` the previous one, `$OLDPWD`. Otherwise, e.g. with an unknown user, it's kept as it is.
+/// Returns any leftover input
+fn expand_tilde(
+    input_buffer: String,
+    curr_expanded_buffer: &mut String,
+    assignment: bool,
+) -> String {
+    let end = input_buffer
+        .find(|c: char| c == '/' || c.is_whitespace() || (assignment && c == ':'))
+        .unwrap_or(input_buffer.len());
+    let name = &input_buffer[..end];
+
+    let directory = if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || matches!(c, '.' | '_' | '-' | '+'))
+    {
+        //Quoted or with another expansion in it
+        None
+    } else {
+        match name {
+            "" => state::variable("HOME")
+                .or_else(|| dirs::home_dir().map(|home| home.to_string_lossy().into_owned())),
+            "+" => env::current_dir()
+                .ok()
+                .map(|dir| dir.to_string_lossy().into_owned()),
+            "-" => state::variable("OLDPWD"),
+            _ => unistd::User::from_name(name)
+                .ok()
+                .flatten()
+                .map(|user| user.dir.to_string_lossy().into_owned()),
+        }
+    };
+
+    match directory {
+        Some(directory) => {
+            curr_expanded_buffer.push_str(&directory);
+            input_buffer[end..].to_owned()
+        }
+        None => {
+            curr_expanded_buffer.push('~');
+            input_buffer
+        }
+    }
+}
+
+/// Whether what follows a `$` is `@` or `{@}`, see `expand_positional_parameters`
+fn is_all_positional_parameters(rest: impl Iterator<Item = char>) -> bool {
+    let start: String = rest.take(3).collect();
+    start.starts_with('@') || start == "{@}"
+}
+
+/// `$@` or `${@}`, each positional parameter becomes a word of its own, even inside double quotes.
+/// The first one is joined to what came before it in the current word, the last one to what comes after it
+fn expand_positional_parameters(curr_expanded_buffer: &mut String, words: &mut Vec<String>) {
+    let mut params = state::positional_parameters().into_iter().peekable();
+    while let Some(param) = params.next() {
+        curr_expanded_buffer.push_str(&param);
+        if params.peek().is_some() {
+            words.push(curr_expanded_buffer.as_str().to_string());
+            curr_expanded_buffer.clear();
+        }
+    }
+}
+
+/// Adds `word` to `words`. If it has unquoted wildcards, and `glob` is on, the paths it matches are added instead,
+/// see `glob`. `quoted` are the parts of the word that were quoted.
+///
+/// Like in bash, a word that matches nothing is kept as it is, removed with `nullglob`, or an error with `failglob`
+fn push_word(
+    words: &mut Vec<String>,
+    word: &str,
+    quoted: &[Range<usize>],
+    glob: bool,
+) -> Result<(), ExpansionError> {
+    if glob {
+        let mut pattern = String::with_capacity(word.len());
+        for (i, c) in word.char_indices() {
+            if matches!(c, '*' | '?' | '[' | ']' | '\\') && quoted.iter().any(|r| r.contains(&i)) {
+                pattern.push('\\');
+            }
+            pattern.push(c);
+        }
+        if pattern::has_wildcards(&pattern) {
+            let mut paths = glob::expand(&pattern);
+            if !paths.is_empty() || state::option(ShellOption::NullGlob) {
+                words.append(&mut paths);
+                return Ok(());
+            }
+            if state::option(ShellOption::FailGlob) {
+                return Err(ExpansionError::WildcardMatch(format!("no match: {}", word)));
+            }
+        }
+    }
+    words.push(word.to_owned());
+    Ok(())
 }
 
 /// Supresses all expansions
-/// Gets ownership of a String w/ all input provided from the user so far.
-/// Reads all input, including new lines if necessary, until a pair to `'` is found
+/// Gets ownership of a String w/ all input provided from the user so far, until a pair to `'` is found.
+/// The parser only accepts input with balanced quotes, which may span several lines
 /// Leftover input *after* the `'`, if any, is returned and should be used to update the iterator in the main loop
 fn single_quote_supression(curr_input_buffer: String, curr_expanded_buffer: &mut String) -> String {
-    let mut found_pair = false;
-    let mut next_input_buffer = String::new();
     let mut curr_input_iter = curr_input_buffer.chars();
 
-    while !found_pair {
-        while let Some(c) = curr_input_iter.next() {
-            match c {
-                '\'' => {
-                    found_pair = true;
-                    break;
-                }
-                _ => {
-                    //preserve all characters including whitespace
-                    curr_expanded_buffer.push(c);
-                }
+    for c in curr_input_iter.by_ref() {
+        match c {
+            '\'' => break,
+            _ => {
+                //preserve all characters including whitespace
+                curr_expanded_buffer.push(c);
             }
         }
-        if !found_pair {
-            dbg!("Reading more input");
-            next_input_buffer.clear();
-            read_line_into_secondary_prompt(&mut next_input_buffer);
-            curr_input_iter = next_input_buffer.chars();
-        }
     }
     curr_input_iter.collect()
 }
 
 /// Supresses all expansions, with the exception of $ and \ expansion
-/// Gets ownership of a String w/ all input provided from the user so far.
-/// Reads all input, including new lines if necessary, until a pair to `"` is found
+/// Gets ownership of a String w/ all input provided from the user so far, until a pair to `"` is found.
+/// The parser only accepts input with balanced quotes, which may span several lines
 /// Leftover input *after* the `"`, if any, is returned and should be used to update the iterator in the main loop
+///
+/// For the body of a here-document (`here_doc`), `"` isn't special and the whole input is expanded
 fn double_quote_supression(
     curr_input_buffer: String,
     curr_expanded_buffer: &mut String,
-) -> Result<String, env::VarError> {
-    let mut found_pair = false;
-    let mut next_input_buffer = String::new();
+    words: &mut Vec<String>,
+    here_doc: bool,
+) -> Result<String, ExpansionError> {
     let mut curr_input_iter = curr_input_buffer.chars();
     let mut leftover_buffer = String::new();
 
-    while !found_pair {
-        while let Some(c) = curr_input_iter.next() {
-            dbg!(&curr_expanded_buffer);
-            match c {
-                '$' => {
-                    curr_input_iter = set_owner_get_chars(
-                        expand_env_var(curr_input_iter.by_ref().collect(), curr_expanded_buffer)?,
-                        &mut leftover_buffer,
-                    );
+    while let Some(c) = curr_input_iter.next() {
+        match c {
+            '$' if is_all_positional_parameters(curr_input_iter.clone()) => {
+                if curr_input_iter.next() == Some('{') {
+                    curr_input_iter.nth(1);
                 }
-                '\"' => {
-                    found_pair = true;
-                    break;
+                expand_positional_parameters(curr_expanded_buffer, words);
+            }
+            '$' if curr_input_iter.clone().next() == Some('(') => {
+                curr_input_iter.next();
+                curr_input_iter = set_owner_get_chars(
+                    expand_command_substitution(
+                        curr_input_iter.by_ref().collect(),
+                        curr_expanded_buffer,
+                        None,
+                    )?,
+                    &mut leftover_buffer,
+                );
+            }
+            '`' => {
+                curr_input_iter = set_owner_get_chars(
+                    expand_backquoted_substitution(
+                        curr_input_iter.by_ref().collect(),
+                        curr_expanded_buffer,
+                        None,
+                    )?,
+                    &mut leftover_buffer,
+                );
+            }
+            '$' => {
+                curr_input_iter = set_owner_get_chars(
+                    expand_env_var(curr_input_iter.by_ref().collect(), curr_expanded_buffer)?,
+                    &mut leftover_buffer,
+                );
+            }
+            //Only escapes the characters that are special inside double quotes
+            '\\' => match curr_input_iter.clone().next() {
+                Some('"') if here_doc => curr_expanded_buffer.push(c),
+                Some(next @ ('$' | '`' | '"' | '\\')) => {
+                    curr_input_iter.next();
+                    curr_expanded_buffer.push(next);
                 }
-                _ => {
-                    //preserve all characters including whitespace
-                    curr_expanded_buffer.push(c);
+                //Line continuation
+                Some('\n') if here_doc => {
+                    curr_input_iter.next();
                 }
+                _ => curr_expanded_buffer.push(c),
+            },
+            '\"' if !here_doc => break,
+            _ => {
+                //preserve all characters including whitespace
+                curr_expanded_buffer.push(c);
             }
         }
-        if !found_pair {
-            dbg!("Reading more input");
-            next_input_buffer.clear();
-            read_line_into_secondary_prompt(&mut next_input_buffer);
-            curr_input_iter = next_input_buffer.chars();
-        }
     }
     Ok(curr_input_iter.collect())
 }
 
 /// Stores the a value (usually from a function) into a **longer living** owner variable. Returns the chars iterator of the buffer
-fn set_owner_get_chars(value: String, owner: &mut String) -> std::str::Chars {
+fn set_owner_get_chars(value: String, owner: &mut String) -> std::str::Chars<'_> {
     *owner = value;
     owner.chars()
 }
@@ -521,16 +1085,18 @@ fn set_owner_get_chars(value: String, owner: &mut String) -> std::str::Chars {
 fn set_owner_get_chars_peekable(
     value: String,
     owner: &mut String,
-) -> std::iter::Peekable<std::str::Chars> {
+) -> std::iter::Peekable<std::str::Chars<'_>> {
     set_owner_get_chars(value, owner).peekable()
 }
 
+#[cfg(test)]
 mod test {
     use super::*;
+    use std::fs;
     #[test]
     fn success_expand_env() {
         let key = "SOME_KEY";
-        env::set_var(key, "VALUE");
+        state::export_variable(key, Some("VALUE".to_owned())).unwrap();
 
         let mut input_expanded = vec![];
 
@@ -541,6 +1107,43 @@ mod test {
         );
     }
 
+    #[test]
+    fn success_expand_special_parameters() {
+        let mut input_expanded = vec![];
+
+        assert!(expand("echo $$ \"$?\"x", &mut input_expanded).is_ok());
+        assert_eq!(
+            input_expanded,
+            vec![
+                String::from("echo"),
+                std::process::id().to_string(),
+                format!("{}x", state::last_status())
+            ]
+        );
+    }
+
+    #[test]
+    fn success_expand_positional_parameters() {
+        state::set_positional_parameters(
+            "script.sh".to_owned(),
+            vec!["a".to_owned(), "b c".to_owned()],
+        );
+        let mut input_expanded = vec![];
+
+        assert!(expand("echo $0 $# \"x$@y\" $1", &mut input_expanded).is_ok());
+        state::set_positional_parameters(String::new(), vec![]);
+        assert_eq!(
+            input_expanded,
+            vec!["echo", "script.sh", "2", "xa", "b cy", "a"]
+        );
+
+        state::set_positional_parameters(String::new(), vec!["a".to_owned(), "b c".to_owned()]);
+        let mut input_expanded = vec![];
+        assert!(expand("echo \"x${@}y\" ${@}", &mut input_expanded).is_ok());
+        state::set_positional_parameters(String::new(), vec![]);
+        assert_eq!(input_expanded, vec!["echo", "xa", "b cy", "a", "b c"]);
+    }
+
     #[test]
     fn fail_expand_env_non_existing_env() {
         let mut input_expanded = vec![];
@@ -563,7 +1166,7 @@ mod test {
     #[test]
     fn success_double_quote() {
         let key = "SOME_KEY";
-        env::set_var(key, "VALUE");
+        state::export_variable(key, Some("VALUE".to_owned())).unwrap();
 
         let mut input_expanded = vec![];
 
@@ -575,6 +1178,219 @@ mod test {
         );
     }
 
+    #[test]
+    fn success_double_quote_escapes() {
+        let mut input_expanded = vec![];
+
+        assert!(expand("\"\\$HOME \\\" \\\\ \\a\"", &mut input_expanded).is_ok());
+
+        assert_eq!(input_expanded, vec![String::from("$HOME \" \\ \\a")]);
+    }
+
+    #[test]
+    fn success_expand_shell_variable() {
+        state::set_variable("SHRIMP_TEST_SHELL_VAR", "a b".to_owned()).unwrap();
+
+        let mut input_expanded = vec![];
+
+        assert!(expand("$SHRIMP_TEST_SHELL_VAR", &mut input_expanded).is_ok());
+
+        assert_eq!(input_expanded, vec![String::from("a b")]);
+        assert!(!state::exported_variables().contains_key("SHRIMP_TEST_SHELL_VAR"));
+    }
+
+    #[test]
+    fn success_braced_parameters() {
+        state::set_variable("SHRIMP_TEST_PATH", "/usr/lib/a.tar.gz".to_owned()).unwrap();
+        state::set_variable("SHRIMP_TEST_EMPTY", String::new()).unwrap();
+        let _ = state::unset_variable("SHRIMP_TEST_UNSET");
+
+        let cases = [
+            ("${SHRIMP_TEST_PATH}x", "/usr/lib/a.tar.gzx"),
+            ("${#SHRIMP_TEST_PATH}", "17"),
+            ("${SHRIMP_TEST_UNSET:-a b}", "a b"),
+            (
+                "\"${SHRIMP_TEST_EMPTY:-$SHRIMP_TEST_PATH}\"",
+                "/usr/lib/a.tar.gz",
+            ),
+            ("${SHRIMP_TEST_EMPTY-x}", ""),
+            ("${SHRIMP_TEST_PATH:+set}", "set"),
+            ("${SHRIMP_TEST_EMPTY:+set}", ""),
+            ("${SHRIMP_TEST_PATH#*/}", "usr/lib/a.tar.gz"),
+            ("${SHRIMP_TEST_PATH##*/}", "a.tar.gz"),
+            ("${SHRIMP_TEST_PATH%.*}", "/usr/lib/a.tar"),
+            ("${SHRIMP_TEST_PATH%%.*}", "/usr/lib/a"),
+            ("${SHRIMP_TEST_PATH%'.*'}", "/usr/lib/a.tar.gz"),
+            ("${SHRIMP_TEST_PATH:5:3}", "lib"),
+            ("${SHRIMP_TEST_PATH: -2}", "gz"),
+            ("${SHRIMP_TEST_PATH:1:-7}", "usr/lib/a"),
+            ("${SHRIMP_TEST_PATH/a/A}", "/usr/lib/A.tar.gz"),
+            ("${SHRIMP_TEST_PATH//a/A}", "/usr/lib/A.tAr.gz"),
+            ("${SHRIMP_TEST_PATH/#\\/usr/X}", "X/lib/a.tar.gz"),
+            ("${SHRIMP_TEST_PATH/%.gz}", "/usr/lib/a.tar"),
+        ];
+        for (input, expected) in cases {
+            let mut input_expanded = vec![];
+            expand(input, &mut input_expanded).unwrap();
+            assert_eq!(input_expanded.join(" "), expected, "{}", input);
+        }
+
+        let mut input_expanded = vec![];
+        expand("${SHRIMP_TEST_UNSET:=new}", &mut input_expanded).unwrap();
+        assert_eq!(input_expanded, vec!["new"]);
+        assert_eq!(state::variable("SHRIMP_TEST_UNSET"), Some("new".to_owned()));
+        state::unset_variable("SHRIMP_TEST_UNSET").unwrap();
+    }
+
+    #[test]
+    fn fail_braced_parameters() {
+        let _ = state::unset_variable("SHRIMP_TEST_UNSET");
+        let mut input_expanded = vec![];
+
+        let e = expand("${SHRIMP_TEST_UNSET:?not here}", &mut input_expanded).unwrap_err();
+        assert!(
+            matches!(e, ExpansionError::UnsetParameter(ref s) if s == "SHRIMP_TEST_UNSET: not here")
+        );
+        assert!(matches!(
+            expand("${SHRIMP_TEST_UNSET", &mut input_expanded),
+            Err(ExpansionError::UnmatchedBrace(_))
+        ));
+        assert!(matches!(
+            expand("${SHRIMP_TEST_UNSET!}", &mut input_expanded),
+            Err(ExpansionError::BadSubstitution(_))
+        ));
+        assert!(matches!(
+            expand("${1:=x}", &mut input_expanded),
+            Err(ExpansionError::Assignment(_))
+        ));
+    }
+
+    #[test]
+    fn success_tilde_expansion() {
+        let home = state::variable("HOME").unwrap();
+        let root_home = unistd::User::from_name("root").unwrap().unwrap().dir;
+        let root_home = root_home.to_str().unwrap();
+
+        let mut input_expanded = vec![];
+        assert!(expand(
+            "~ ~/a ~root/b x~ '~' ~nosuchuser_shrimp",
+            &mut input_expanded
+        )
+        .is_ok());
+        assert_eq!(
+            input_expanded,
+            vec![
+                home.clone(),
+                format!("{}/a", home),
+                format!("{}/b", root_home),
+                "x~".to_owned(),
+                "~".to_owned(),
+                "~nosuchuser_shrimp".to_owned()
+            ]
+        );
+
+        let mut input_expanded = vec![];
+        assert!(expand("~+", &mut input_expanded).is_ok());
+        assert_eq!(
+            input_expanded,
+            vec![env::current_dir().unwrap().to_str().unwrap()]
+        );
+
+        assert_eq!(
+            expand_assignment_value("~/bin:~root:a~").unwrap(),
+            format!("{}/bin:{}:a~", home, root_home)
+        );
+    }
+
+    #[test]
+    fn success_here_doc_expansion() {
+        state::export_variable("SHRIMP_TEST_HERE_DOC", Some("a  b".to_owned())).unwrap();
+        assert_eq!(
+            expand_here_doc(
+                "$SHRIMP_TEST_HERE_DOC \"'*'\" \\$x \\\"\n$((1 + 1)) `echo c` a\\\nb\n"
+            )
+            .unwrap(),
+            "a  b \"'*'\" $x \\\"\n2 c ab\n"
+        );
+    }
+
+    #[test]
+    fn success_command_substitution() {
+        let mut input_expanded = vec![];
+        assert!(expand("x$(printf 'a  b\\n\\n')y", &mut input_expanded).is_ok());
+        assert_eq!(input_expanded, vec!["xa", "by"]);
+
+        let mut input_expanded = vec![];
+        assert!(expand(
+            "\"$(printf 'a  b')\" `echo \\`echo c\\``",
+            &mut input_expanded
+        )
+        .is_ok());
+        assert_eq!(input_expanded, vec!["a  b", "c"]);
+
+        let mut input_expanded = vec![];
+        assert!(expand("$(echo $(echo \"a)\"))", &mut input_expanded).is_ok());
+        assert_eq!(input_expanded, vec!["a)"]);
+
+        assert_eq!(expand_assignment_value("$(printf 'a  b')").unwrap(), "a  b");
+
+        //Directory changes don't leak out
+        let cwd = env::current_dir().unwrap();
+        let mut input_expanded = vec![];
+        assert!(expand("$(cd /; pwd)", &mut input_expanded).is_ok());
+        assert_eq!(input_expanded, vec!["/"]);
+        assert_eq!(env::current_dir().unwrap(), cwd);
+
+        //Neither do variables, options or `exit`, whose status becomes `$?`
+        let mut input_expanded = vec![];
+        assert!(expand(
+            "a$(SHRIMP_TEST_SUBSHELL=1; export SHRIMP_TEST_SUBSHELL; set -C; exit 3)b",
+            &mut input_expanded
+        )
+        .is_ok());
+        assert_eq!(input_expanded, vec!["ab"]);
+        assert_eq!(state::last_status(), 3);
+        assert_eq!(state::variable("SHRIMP_TEST_SUBSHELL"), None);
+        assert!(!state::option(ShellOption::NoClobber));
+        state::set_last_status(&StepOutput::success());
+    }
+
+    #[test]
+    fn success_arithmetic_expansion() {
+        state::set_variable("SHRIMP_TEST_COUNTER", "2".to_owned()).unwrap();
+        let mut input_expanded = vec![];
+        assert!(expand(
+            "$((SHRIMP_TEST_COUNTER += 1)) \"$(( $SHRIMP_TEST_COUNTER * (1 + $(echo 1)) ))\"",
+            &mut input_expanded
+        )
+        .is_ok());
+        assert_eq!(input_expanded, vec!["3", "6"]);
+        assert_eq!(state::variable("SHRIMP_TEST_COUNTER"), Some("3".to_owned()));
+        state::unset_variable("SHRIMP_TEST_COUNTER").unwrap();
+
+        assert!(matches!(
+            expand("$((1 / 0))", &mut input_expanded),
+            Err(ExpansionError::Arithmetic(_))
+        ));
+    }
+
+    #[test]
+    fn fail_command_substitution() {
+        let mut input_expanded = vec![];
+        assert!(matches!(
+            expand("$(echo", &mut input_expanded),
+            Err(ExpansionError::Substitution(_))
+        ));
+        assert!(matches!(
+            expand("`echo", &mut input_expanded),
+            Err(ExpansionError::Substitution(_))
+        ));
+        assert!(matches!(
+            expand("$(echo |)", &mut input_expanded),
+            Err(ExpansionError::Substitution(_))
+        ));
+    }
+
     #[test]
     fn success_wildcard_pathname() {
         let mut input_expanded = vec![];
@@ -640,19 +1456,34 @@ mod test {
         assert!(result_2.is_ok());
         assert_eq!(input_expanded_2, vec!["./tests/dir/file_1.txt".to_owned()]);
 
+        //Relative to the parent of the repository, whatever its directory is named
+        let repo_dir = env::current_dir().unwrap();
+        let repo_dir = repo_dir.file_name().unwrap().to_str().unwrap();
         let mut input_expanded = vec![];
-        assert!(expand("../Shrimp/src/main*s", &mut input_expanded).is_ok());
-        assert_eq!(input_expanded, vec!["../Shrimp/src/main.rs".to_owned()]);
+        assert!(expand(&format!("../{}/src/main*s", repo_dir), &mut input_expanded).is_ok());
+        assert_eq!(input_expanded, vec![format!("../{}/src/main.rs", repo_dir)]);
     }
 
     #[test]
-    fn fail_wildcard_non_existing_base_dir() {
-        let mut input_expanded_1 = vec![];
+    fn wildcard_without_match() {
+        let pattern = "../a_folder_that_does_not_exist/*.txt";
 
-        let result_1 = expand(
-            "../a_folder_that_does_not_exist/*.txt",
-            &mut input_expanded_1,
-        );
-        assert!(result_1.is_err());
+        //Kept as it is by default, quotes removed
+        let mut input_expanded = vec![];
+        assert!(expand("'a b'*.none", &mut input_expanded).is_ok());
+        assert!(expand(pattern, &mut input_expanded).is_ok());
+        assert_eq!(input_expanded, vec!["a b*.none", pattern]);
+
+        state::set_option(ShellOption::NullGlob, true);
+        let mut input_expanded = vec![];
+        let result = expand(pattern, &mut input_expanded);
+        state::set_option(ShellOption::NullGlob, false);
+        assert!(result.is_ok());
+        assert!(input_expanded.is_empty());
+
+        state::set_option(ShellOption::FailGlob, true);
+        let result = expand(pattern, &mut input_expanded);
+        state::set_option(ShellOption::FailGlob, false);
+        assert!(matches!(result, Err(ExpansionError::WildcardMatch(_))));
     }
 }
@@ -0,0 +1,473 @@
+//! Job control: the table of Pipelines running in the background or stopped, and the handover of the terminal
+//! between the shell and the foreground Pipeline.
+//!
+//! Like the current directory, the job table belongs to the whole shell process, so it lives in a static that
+//! the built-ins (`jobs`, `fg`, `bg`) can reach.
+//!
+//! Job control is only enabled when the shell runs on a terminal, see `init`. Otherwise Pipelines stay in the
+//! shell's process group, and background ones just don't wait.
+use crate::{
+    pipeline::{PipelineState, RunningPipeline},
+    signal,
+    step::StepOutput,
+};
+use nix::{
+    fcntl::{fcntl, FcntlArg},
+    sys::signal::{killpg, Signal},
+    unistd::{getpgrp, isatty, setpgid, tcgetpgrp, tcsetpgrp, Pid},
+};
+use std::{
+    fmt,
+    io::{Error, ErrorKind, Result},
+    os::unix::io::RawFd,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+
+/// Duplicate of the terminal's file descriptor, -1 when job control is disabled
+static TERMINAL: AtomicI32 = AtomicI32::new(-1);
+
+static JOBS: Mutex<JobTable> = Mutex::new(JobTable {
+    jobs: Vec::new(),
+    last_touch: 0,
+    last_background_pid: None,
+});
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done,
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        //`pad`, so the states can be aligned
+        match self {
+            JobState::Running => f.pad("Running"),
+            JobState::Stopped => f.pad("Stopped"),
+            JobState::Done => f.pad("Done"),
+        }
+    }
+}
+
+/// A Pipeline, or a Sublist with several Pipelines (e.g. `a && b &`) running in a copy of the shell, see
+/// `RunningPipeline::forked`
+#[derive(Debug)]
+pub struct Job {
+    pub id: usize,
+    /// Command line as typed by the user
+    pub command: String,
+    pub state: JobState,
+    process: RunningPipeline,
+    /// When the Job was last started, stopped or moved, used to find the current (`%+`) and previous (`%-`) Jobs
+    touch: u64,
+}
+
+#[derive(Debug)]
+struct JobTable {
+    jobs: Vec<Job>,
+    last_touch: u64,
+    last_background_pid: Option<Pid>,
+}
+
+impl JobTable {
+    fn insert(
+        &mut self,
+        id: Option<usize>,
+        command: String,
+        state: JobState,
+        process: RunningPipeline,
+    ) -> usize {
+        let id = id.unwrap_or_else(|| self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1);
+        self.last_touch += 1;
+        let job = Job {
+            id,
+            command,
+            state,
+            process,
+            touch: self.last_touch,
+        };
+        let position = self
+            .jobs
+            .iter()
+            .position(|j| j.id > id)
+            .unwrap_or(self.jobs.len());
+        self.jobs.insert(position, job);
+        id
+    }
+
+    fn remove(&mut self, id: usize) -> Option<Job> {
+        let position = self.jobs.iter().position(|j| j.id == id)?;
+        Some(self.jobs.remove(position))
+    }
+
+    /// Ids of the current and previous Jobs
+    fn current(&self) -> (Option<usize>, Option<usize>) {
+        let mut by_touch: Vec<&Job> = self.jobs.iter().collect();
+        by_touch.sort_by_key(|j| std::cmp::Reverse(j.touch));
+        (
+            by_touch.first().map(|j| j.id),
+            by_touch.get(1).map(|j| j.id),
+        )
+    }
+
+    /// `+` for the current Job, `-` for the previous one, as shown by `jobs`
+    fn marker(&self, id: usize) -> char {
+        match self.current() {
+            (Some(current), _) if current == id => '+',
+            (_, Some(previous)) if previous == id => '-',
+            _ => ' ',
+        }
+    }
+
+    fn describe(&self, job: &Job) -> String {
+        format_job(job.id, self.marker(job.id), &job.state, &job.command)
+    }
+}
+
+/// Line describing a Job, as shown by `jobs`
+fn format_job(id: usize, marker: char, state: &dyn fmt::Display, command: &str) -> String {
+    format!("[{}]{}  {:<23} {}", id, marker, state, command)
+}
+
+fn table() -> MutexGuard<'static, JobTable> {
+    JOBS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Enables job control if the shell is running on a terminal: puts the shell in its own process group
+/// and takes the terminal. Expects the signals sent by the terminal to be ignored already, see `signal::init`
+pub fn init() {
+    if !isatty(0).unwrap_or(false) {
+        return;
+    }
+
+    //Wait until we are in the foreground, like any other job
+    loop {
+        match tcgetpgrp(0) {
+            Ok(pgrp) if pgrp == getpgrp() => break,
+            Ok(_) => {
+                let _ = killpg(getpgrp(), Signal::SIGTTIN);
+            }
+            Err(_) => return,
+        }
+    }
+
+    //Fails if we're already a process group (or session) leader, which is fine
+    let _ = setpgid(Pid::from_raw(0), Pid::from_raw(0));
+    if tcsetpgrp(0, getpgrp()).is_err() {
+        return;
+    }
+
+    //Kept out of the way of redirections, and not inherited by the Steps
+    if let Ok(fd) = fcntl(0, FcntlArg::F_DUPFD_CLOEXEC(10)) {
+        TERMINAL.store(fd, Ordering::SeqCst);
+    }
+}
+
+/// Disables job control in a child of the shell, see `step::fork`: the Pipelines it starts stay in its own process
+/// group, and it leaves the terminal alone
+pub fn disable() {
+    TERMINAL.store(-1, Ordering::SeqCst);
+}
+
+/// Whether Pipelines run in their own process groups, see `init`
+pub fn job_control() -> bool {
+    terminal().is_some()
+}
+
+/// File descriptor of the terminal controlled by the shell, if job control is enabled
+pub fn terminal() -> Option<RawFd> {
+    match TERMINAL.load(Ordering::SeqCst) {
+        -1 => None,
+        fd => Some(fd),
+    }
+}
+
+/// Pid of the last process started in the background, for `$!`
+pub fn last_background_pid() -> Option<Pid> {
+    table().last_background_pid
+}
+
+fn give_terminal(pgid: Option<Pid>) {
+    if let (Some(terminal), Some(pgid)) = (terminal(), pgid) {
+        let _ = tcsetpgrp(terminal, pgid);
+    }
+}
+
+fn reclaim_terminal() {
+    if let Some(terminal) = terminal() {
+        let _ = tcsetpgrp(terminal, getpgrp());
+    }
+}
+
+/// Exit code of a Pipeline stopped by Ctrl-Z
+fn stopped_output() -> StepOutput {
+    StepOutput {
+        success: false,
+        code: Some(signal::exit_code(Signal::SIGTSTP as i32)),
+        signal: None,
+        stdout: vec![],
+        stderr: vec![],
+    }
+}
+
+/// Waits for a Pipeline in the foreground. If it gets stopped it's added to the job table instead
+pub fn wait_foreground(command: String, running: RunningPipeline) -> Result<StepOutput> {
+    wait_job(None, command, running)
+}
+
+/// Gives the terminal to the Job and waits for it to finish or stop, then takes the terminal back.
+/// A Job that was already in the table keeps its id
+fn wait_job(
+    id: Option<usize>,
+    command: String,
+    mut process: RunningPipeline,
+) -> Result<StepOutput> {
+    give_terminal(process.pgid());
+    let state = process.wait(true);
+    reclaim_terminal();
+    let state = state?;
+
+    if state == PipelineState::Stopped {
+        let mut table = table();
+        let id = table.insert(id, command, JobState::Stopped, process);
+        let job = table.jobs.iter().find(|j| j.id == id).unwrap();
+        eprintln!("\n{}", table.describe(job));
+        return Ok(stopped_output());
+    }
+
+    let out = process.finish()?;
+    if let Some(message) = out.signal.and_then(signal::termination_message) {
+        eprintln!("{}", message);
+    }
+    Ok(out)
+}
+
+/// Adds a Job that was started in the background, printing its id and pid
+pub fn background(command: String, process: RunningPipeline) -> usize {
+    let mut table = table();
+    let pid = process.pids().last().copied();
+    let id = table.insert(None, command, JobState::Running, process);
+    match pid {
+        Some(pid) => {
+            table.last_background_pid = Some(pid);
+            eprintln!("[{}] {}", id, pid);
+        }
+        None => eprintln!("[{}]", id),
+    }
+    id
+}
+
+/// Updates the state of every Job, returning the lines reporting the ones that changed. Finished Jobs are removed
+/// from the table.
+///
+/// Called before showing the prompt, so the messages don't get mixed with the output of the foreground
+pub fn notify() -> Vec<String> {
+    let mut table = table();
+    let mut lines = vec![];
+    let mut i = 0;
+    while i < table.jobs.len() {
+        let previous = table.jobs[i].state;
+        let state = match table.jobs[i].process.wait(false) {
+            //Nothing changed, a stopped Job is only resumed by `fg` / `bg`
+            Ok(PipelineState::Running) => previous,
+            Ok(PipelineState::Stopped) => JobState::Stopped,
+            Ok(PipelineState::Done) | Err(_) => JobState::Done,
+        };
+        table.jobs[i].state = state;
+
+        if state == JobState::Done {
+            let marker = table.marker(table.jobs[i].id);
+            let job = table.jobs.remove(i);
+            let state = match job.process.finish() {
+                Ok(out) => match out.signal.and_then(signal::termination_message) {
+                    Some(message) => message,
+                    None if !out.success => format!("Exit {}", out.status()),
+                    None => JobState::Done.to_string(),
+                },
+                Err(e) => e.to_string(),
+            };
+            lines.push(format_job(job.id, marker, &state, &job.command));
+            continue;
+        }
+
+        if state != previous {
+            table.last_touch += 1;
+            table.jobs[i].touch = table.last_touch;
+            lines.push(table.describe(&table.jobs[i]));
+        }
+        i += 1;
+    }
+    lines
+}
+
+/// Number of Jobs in the table, for the prompt
+pub fn count() -> usize {
+    table().jobs.len()
+}
+
+/// Lines shown by the `jobs` built-in
+pub fn list() -> Vec<String> {
+    let table = table();
+    table.jobs.iter().map(|j| table.describe(j)).collect()
+}
+
+/// Finds the id of the Job identified by `spec`: `%N` (or just `N`), `%+` / `%%` / `%` for the current Job,
+/// `%-` for the previous one, and `%prefix` for the Job whose command starts with `prefix`.
+/// Without `spec`, the current Job
+pub fn find(spec: Option<&str>) -> Result<usize> {
+    let table = table();
+    let (current, previous) = table.current();
+    let spec_str = spec.unwrap_or("%+");
+    let no_such_job = || {
+        let message = match spec {
+            Some(s) => format!("{}: no such job", s),
+            None => "no current job".to_owned(),
+        };
+        Error::new(ErrorKind::NotFound, message)
+    };
+
+    let id = match spec_str.strip_prefix('%').unwrap_or(spec_str) {
+        "" | "+" | "%" => current,
+        "-" => previous,
+        n if n.parse::<usize>().is_ok() => n.parse().ok(),
+        prefix => {
+            let mut matches = table.jobs.iter().filter(|j| j.command.starts_with(prefix));
+            match (matches.next(), matches.next()) {
+                (Some(j), None) => Some(j.id),
+                (Some(_), Some(_)) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{}: ambiguous job spec", spec_str),
+                    ))
+                }
+                _ => None,
+            }
+        }
+    };
+
+    id.filter(|id| table.jobs.iter().any(|j| j.id == *id))
+        .ok_or_else(no_such_job)
+}
+
+/// Moves the Job to the foreground, resuming it if stopped, and waits for it.
+/// `on_start` gets the command of the Job right before it's resumed
+pub fn foreground(id: usize, on_start: impl FnOnce(&str)) -> Result<StepOutput> {
+    let job = table()
+        .remove(id)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("%{}: no such job", id)))?;
+
+    on_start(&job.command);
+    if let Some(pgid) = job.process.pgid() {
+        give_terminal(Some(pgid));
+        killpg(pgid, Signal::SIGCONT)?;
+    }
+    wait_job(Some(job.id), job.command, job.process)
+}
+
+/// Resumes a stopped Job in the background, returning the line to report it
+pub fn resume_background(id: usize) -> Result<String> {
+    let mut table = table();
+    table.last_touch += 1;
+    let touch = table.last_touch;
+    let job = table
+        .jobs
+        .iter_mut()
+        .find(|j| j.id == id)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("%{}: no such job", id)))?;
+
+    if job.state != JobState::Stopped {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("job {} already in background", id),
+        ));
+    }
+    if let Some(pgid) = job.process.pgid() {
+        killpg(pgid, Signal::SIGCONT)?;
+    }
+    job.state = JobState::Running;
+    job.touch = touch;
+
+    let job = table.jobs.iter().find(|j| j.id == id).unwrap();
+    Ok(format!(
+        "[{}]{} {} &",
+        job.id,
+        table.marker(job.id),
+        job.command
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::step;
+
+    fn forked_job() -> RunningPipeline {
+        let run = || {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            0
+        };
+        RunningPipeline::forked(step::fork(run, None, false).unwrap())
+    }
+
+    #[test]
+    fn job_specs() {
+        let first = background("sleep 1".to_owned(), forked_job());
+        let second = background("true".to_owned(), forked_job());
+
+        assert_eq!(find(Some(&format!("%{}", first))).unwrap(), first);
+        assert_eq!(find(Some(&second.to_string())).unwrap(), second);
+        assert_eq!(find(Some("%+")).unwrap(), second);
+        assert_eq!(find(Some("%%")).unwrap(), second);
+        assert_eq!(find(None).unwrap(), second);
+        assert_eq!(find(Some("%-")).unwrap(), first);
+        assert_eq!(find(Some("%sl")).unwrap(), first);
+        assert_eq!(find(Some("%99")).unwrap_err().kind(), ErrorKind::NotFound);
+
+        //Finished Jobs are reported and removed
+        foreground(first, |_| {}).unwrap();
+        foreground(second, |_| {}).unwrap();
+        assert!(find(Some(&format!("%{}", first))).is_err());
+    }
+
+    #[test]
+    fn fg_outputs_the_command() {
+        let id = background("sleep 1".to_owned(), forked_job());
+        let out = crate::builtin::Builtin::new("fg")
+            .arg(&format!("%{}", id))
+            .run(&[])
+            .unwrap();
+        assert!(out.success);
+        assert_eq!(out.stdout, b"sleep 1\n");
+    }
+
+    #[test]
+    fn background_builtin_outside_the_shell() {
+        let previous_dir = std::env::current_dir().unwrap();
+        crate::parser::parse("cd / &").unwrap().run();
+        let id = find(None).unwrap();
+        assert!(
+            foreground(id, |command| assert_eq!(command, "cd /"))
+                .unwrap()
+                .success
+        );
+        assert_eq!(std::env::current_dir().unwrap(), previous_dir);
+    }
+
+    #[test]
+    fn background_forked_job_listed() {
+        let id = background("sleep 1 && true".to_owned(), forked_job());
+        assert!(list().iter().any(|l| l.ends_with("sleep 1 && true")));
+        let jobs = crate::builtin::Builtin::new("jobs").run(&[]).unwrap();
+        assert!(String::from_utf8(jobs.stdout)
+            .unwrap()
+            .contains("sleep 1 && true"));
+
+        let out = foreground(id, |command| assert_eq!(command, "sleep 1 && true")).unwrap();
+        assert!(out.success);
+        assert!(!list().iter().any(|l| l.ends_with("sleep 1 && true")));
+    }
+}
@@ -1,15 +1,31 @@
+pub mod arithmetic;
+pub mod ast;
+pub mod brace;
 pub mod builtin;
 pub mod builtin_functions;
 pub mod config;
+pub mod error;
+pub mod glob;
+pub mod helper;
 pub mod input_handler;
+pub mod job;
+pub mod network;
+pub mod parser;
+pub mod pattern;
 pub mod pipeline;
+pub mod prompt;
 pub mod redirection;
+pub mod signal;
+pub mod state;
 pub mod step;
 
 pub use builtin::*;
 pub use builtin_functions::*;
 pub use config::*;
+pub use error::*;
 pub use input_handler::*;
+pub use job::*;
+pub use parser::*;
 pub use pipeline::*;
 pub use redirection::*;
 pub use step::*;
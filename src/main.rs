@@ -1,27 +1,102 @@
 use shrimp::{
     input_handler::{InputHandler, InputHandlingError},
-    Config, Pipeline,
+    job, signal, state, Config, ShellError, StepOutput,
+};
+use std::{
+    env,
+    fs::File,
+    io::{self, BufReader, Cursor, IsTerminal},
+    path::PathBuf,
+    process,
 };
 
-fn main() {
-    let mut input_handler = InputHandler::new(Config::new());
+const USAGE: &str =
+    "Usage: shrimp [--norc | --rcfile file] [-c command [name [args...]] | script [args...]]";
 
+/// Where the commands come from, depending on how the shell was invoked
+enum Mode {
+    /// No arguments and a terminal on stdin
+    Interactive,
+    /// `shrimp -c 'command' [name [args...]]`
+    Command(String),
+    /// `shrimp script.sh [args...]`, or `#!/usr/bin/env shrimp`
+    Script(String),
+    /// No arguments, with commands piped into stdin
+    Stdin,
+}
+
+/// Startup script of an interactive shell
+enum RcFile {
+    /// `shrimprc` in the config directory
+    Default,
+    /// `--rcfile file`
+    Path(String),
+    /// `--norc`
+    None,
+}
+
+/// Parses the arguments of the shell, returning the Mode, the rc file, `$0` and the positional parameters
+fn parse_args(mut args: impl Iterator<Item = String>) -> (Mode, RcFile, String, Vec<String>) {
+    let shell_name = args.next().unwrap_or_else(|| "shrimp".to_owned());
+
+    let mut rc_file = RcFile::Default;
+    let mut arg = args.next();
     loop {
-        match input_handler.read_user_input() {
-            // 3. Implement sublists, pipelines separated with && and ||
-            // 4. Implement a List
-            Ok(split_input) => {
-                if let Ok(p) = Pipeline::new(split_input) {
-                    match p.run() {
-                        Ok(_) => {}
-                        Err(msg) => {
-                            eprintln!("{}", msg);
-                        }
-                    }
+        match arg.as_deref() {
+            Some("--norc") => rc_file = RcFile::None,
+            Some("--rcfile") => match args.next() {
+                Some(path) => rc_file = RcFile::Path(path),
+                None => {
+                    eprintln!("shrimp: --rcfile: option requires an argument\n{}", USAGE);
+                    process::exit(2);
                 }
+            },
+            _ => break,
+        }
+        arg = args.next();
+    }
+
+    let (mode, name, args) = match arg {
+        Some(arg) if arg == "-c" => {
+            let command = args.next().unwrap_or_else(|| {
+                eprintln!("shrimp: -c: option requires an argument\n{}", USAGE);
+                process::exit(2);
+            });
+            let name = args.next().unwrap_or(shell_name);
+            (Mode::Command(command), name, args.collect())
+        }
+        Some(arg) if arg == "-h" || arg == "--help" => {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+        Some(arg) if arg.starts_with('-') && arg != "-" => {
+            eprintln!("shrimp: {}: invalid option\n{}", arg, USAGE);
+            process::exit(2);
+        }
+        Some(script) => (Mode::Script(script.clone()), script, args.collect()),
+        None if io::stdin().is_terminal() => (Mode::Interactive, shell_name, vec![]),
+        None => (Mode::Stdin, shell_name, vec![]),
+    };
+    (mode, rc_file, name, args)
+}
+
+/// Reads and runs commands from the user until the end of input (Ctrl-D)
+fn read_eval_loop(input_handler: &mut InputHandler) {
+    loop {
+        for line in job::notify() {
+            eprintln!("{}", line);
+        }
+        match input_handler.read_user_input() {
+            //The status of every Pipeline is recorded as it finishes, see `state::set_last_status`
+            Ok(list) => {
+                list.run();
             }
             Err(e) => match e {
-                InputHandlingError::Expansion(f) => eprintln!("{}", f),
+                InputHandlingError::Parse(e) => {
+                    let e = ShellError::from(e);
+                    eprintln!("{}", e);
+                    state::set_last_status(&StepOutput::from(e));
+                }
                 InputHandlingError::ReadLine(_) => {
                     break;
                 }
@@ -29,3 +104,51 @@ fn main() {
         }
     }
 }
+
+fn main() {
+    let (mode, rc_file, name, args) = parse_args(env::args());
+    state::set_positional_parameters(name, args);
+
+    let mut input_handler = match mode {
+        Mode::Interactive => {
+            signal::init();
+            job::init();
+            let config = Config::new();
+            //Only interactive shells read it, like bash's .bashrc
+            let rc_file = match rc_file {
+                //Not having one is fine
+                RcFile::Default => Some(config.rc_file()).filter(|f| f.exists()),
+                RcFile::Path(path) => Some(PathBuf::from(path)),
+                RcFile::None => None,
+            };
+            if let Some(rc_file) = rc_file {
+                match File::open(&rc_file) {
+                    Ok(file) => {
+                        InputHandler::from_reader(BufReader::new(file)).run_all();
+                    }
+                    Err(e) => eprintln!("shrimp: {}: {}", rc_file.display(), e),
+                }
+            }
+            InputHandler::new(config)
+        }
+        Mode::Command(command) => InputHandler::from_reader(Cursor::new(command)),
+        Mode::Script(path) => match File::open(&path) {
+            Ok(file) => InputHandler::from_reader(BufReader::new(file)),
+            Err(e) => {
+                let e = ShellError::from(e);
+                eprintln!("shrimp: {}: {}", path, e);
+                process::exit(e.code());
+            }
+        },
+        Mode::Stdin => InputHandler::from_stdin(),
+    };
+
+    if input_handler.is_interactive() {
+        read_eval_loop(&mut input_handler);
+    } else {
+        input_handler.run_all();
+    }
+
+    //Like other shells, the exit status on end of input is the one of the last command
+    process::exit(state::last_status());
+}
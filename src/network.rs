@@ -0,0 +1,259 @@
+//! Network redirections: the target of a redirection can be a socket instead of a file.
+//!
+//! * `tcp://host:port` and `udp://host:port`, IPv6 addresses go between brackets: `tcp://[::1]:80`
+//! * `/dev/tcp/host/port` and `/dev/udp/host/port`, as in bash
+//!
+//! Only targets with one of these explicit forms are sockets, `localhost:80` is still the name of a file.
+//! The socket is connected when the redirection is evaluated, Commands get its file descriptor directly.
+use crate::pipeline::{PipelineReader, PipelineWriter};
+use std::{
+    io::{Error, ErrorKind, Read, Result, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    os::unix::io::{AsFd, OwnedFd},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+/// Where a network redirection connects to
+#[derive(Debug, PartialEq)]
+pub struct Address {
+    pub protocol: Protocol,
+    /// A name to resolve, or an IP address (without brackets)
+    pub host: String,
+    pub port: u16,
+}
+
+impl Address {
+    /// The Address `target` points to, `None` if it's a path in the filesystem.
+    /// Errors if it has the form of a network address but no valid host or port
+    pub fn parse(target: &str) -> Result<Option<Address>> {
+        let forms = [
+            ("tcp://", Protocol::Tcp, ':'),
+            ("udp://", Protocol::Udp, ':'),
+            ("/dev/tcp/", Protocol::Tcp, '/'),
+            ("/dev/udp/", Protocol::Udp, '/'),
+        ];
+        let (protocol, host, port) = match forms.iter().find_map(|(prefix, protocol, separator)| {
+            let rest = target.strip_prefix(prefix)?;
+            Some((*protocol, rest.rsplit_once(*separator)))
+        }) {
+            Some((protocol, Some((host, port)))) => (protocol, host, port),
+            Some((_, None)) => return Err(invalid(target)),
+            None => return Ok(None),
+        };
+
+        let host = host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host);
+        match port.parse() {
+            Ok(port) if !host.is_empty() => Ok(Some(Address {
+                protocol,
+                host: host.to_owned(),
+                port,
+            })),
+            _ => Err(invalid(target)),
+        }
+    }
+
+    /// Connects to the first of the addresses the host resolves to that accepts the connection
+    pub fn connect(&self) -> Result<Socket> {
+        let host = (self.host.as_str(), self.port);
+        match self.protocol {
+            Protocol::Tcp => Ok(Socket::Tcp(TcpStream::connect(host)?)),
+            Protocol::Udp => {
+                let mut last_error = None;
+                for address in host.to_socket_addrs()? {
+                    let local: SocketAddr = match address {
+                        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+                        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+                    };
+                    match UdpSocket::bind(local).and_then(|socket| {
+                        socket.connect(address)?;
+                        Ok(socket)
+                    }) {
+                        Ok(socket) => return Ok(Socket::Udp(UdpStream(socket))),
+                        Err(e) => last_error = Some(e),
+                    }
+                }
+                Err(last_error.unwrap_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{}: could not resolve to any address", self.host),
+                    )
+                }))
+            }
+        }
+    }
+}
+
+fn invalid(target: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("{}: invalid network address", target),
+    )
+}
+
+/// A connected socket, to read from and write to
+#[derive(Debug)]
+pub enum Socket {
+    Tcp(TcpStream),
+    Udp(UdpStream),
+}
+
+impl Socket {
+    pub fn reader(self) -> Box<dyn PipelineReader> {
+        match self {
+            Socket::Tcp(stream) => Box::new(stream),
+            Socket::Udp(stream) => Box::new(stream),
+        }
+    }
+
+    pub fn writer(self) -> Box<dyn PipelineWriter> {
+        match self {
+            Socket::Tcp(stream) => Box::new(stream),
+            Socket::Udp(stream) => Box::new(stream),
+        }
+    }
+}
+
+impl From<Socket> for OwnedFd {
+    fn from(socket: Socket) -> OwnedFd {
+        match socket {
+            Socket::Tcp(stream) => stream.into(),
+            Socket::Udp(UdpStream(socket)) => socket.into(),
+        }
+    }
+}
+
+/// A connected UdpSocket, each write is sent as a datagram and each read receives one
+#[derive(Debug)]
+pub struct UdpStream(UdpSocket);
+
+impl Read for UdpStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.0.recv(buf)
+    }
+}
+
+impl Write for UdpStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0.send(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl PipelineReader for UdpStream {
+    fn fd(&self) -> Option<OwnedFd> {
+        self.0.as_fd().try_clone_to_owned().ok()
+    }
+}
+
+impl PipelineWriter for UdpStream {
+    fn fd(&self) -> Option<OwnedFd> {
+        self.0.as_fd().try_clone_to_owned().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parser, pipeline::OutputBuffer};
+    use std::net::TcpListener;
+
+    fn run(line: &str) -> String {
+        let output = OutputBuffer::new();
+        let mut list = parser::parse(line).unwrap();
+        list.items
+            .remove(0)
+            .sublist
+            .first
+            .expand()
+            .unwrap()
+            .out_writer(Box::new(output.clone()))
+            .run()
+            .unwrap();
+        String::from_utf8(output.take()).unwrap()
+    }
+
+    #[test]
+    fn parse_addresses() {
+        let address = |protocol, host: &str, port| {
+            Some(Address {
+                protocol,
+                host: host.to_owned(),
+                port,
+            })
+        };
+        assert_eq!(
+            Address::parse("tcp://localhost:80").unwrap(),
+            address(Protocol::Tcp, "localhost", 80)
+        );
+        assert_eq!(
+            Address::parse("udp://[::1]:53").unwrap(),
+            address(Protocol::Udp, "::1", 53)
+        );
+        assert_eq!(
+            Address::parse("/dev/tcp/::1/8080").unwrap(),
+            address(Protocol::Tcp, "::1", 8080)
+        );
+        assert_eq!(
+            Address::parse("/dev/udp/10.0.0.1/9").unwrap(),
+            address(Protocol::Udp, "10.0.0.1", 9)
+        );
+        assert_eq!(Address::parse("localhost:80").unwrap(), None);
+        assert_eq!(Address::parse("/dev/null").unwrap(), None);
+        for target in [
+            "tcp://localhost",
+            "tcp://:80",
+            "/dev/udp/host/port",
+            "udp://h:99999",
+        ] {
+            assert_eq!(
+                Address::parse(target).unwrap_err().kind(),
+                ErrorKind::InvalidInput
+            );
+        }
+    }
+
+    #[test]
+    fn tcp_redirections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = std::thread::spawn(move || {
+            let mut received = String::new();
+            listener
+                .accept()
+                .unwrap()
+                .0
+                .read_to_string(&mut received)
+                .unwrap();
+            listener.accept().unwrap().0.write_all(b"hello\n").unwrap();
+            received
+        });
+        run(&format!("echo sent > tcp://127.0.0.1:{}", port));
+        let output = run(&format!("cat < /dev/tcp/127.0.0.1/{}", port));
+
+        assert_eq!(server.join().unwrap(), "sent\n");
+        assert_eq!(output, "hello\n");
+    }
+
+    #[test]
+    fn udp_redirection() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+
+        run(&format!("echo datagram > udp://127.0.0.1:{}", port));
+        let mut buf = [0; 64];
+        let received = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..received], b"datagram\n");
+    }
+}
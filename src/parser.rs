@@ -0,0 +1,1111 @@
+//! Tokenizer and recursive-descent parser for Shrimp's grammar (docs/grammar.md)
+//!
+//! The tokenizer only finds the boundaries of words and operators, quotes and escapes are kept in the
+//! words so they can be handled later by the expansion.
+use crate::{
+    ast::{
+        Assignment, Connector, HereDoc, List, ListItem, Pipeline, Redirect, Span, Step, Sublist,
+        Terminator, Word,
+    },
+    pipeline::Pipe,
+    redirection::Redirection,
+    state,
+};
+use std::{error, fmt, fmt::Display, iter::Peekable, str::CharIndices, str::FromStr};
+
+#[derive(Debug, PartialEq)]
+pub enum TokenKind {
+    Word(String),
+    Redirection(Redirection),
+    Pipe(Pipe),
+    And,
+    Or,
+    Semicolon,
+    Ampersand,
+    Newline,
+    /// Body of the here-document whose `<<` and delimiter come right before it. It's read from the lines after the
+    /// command, but placed here so the parser finds it along with its redirection
+    HereDoc(HereDoc),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParseErrorKind {
+    UnterminatedQuote(char),
+    /// `$(` without its closing `)`
+    UnterminatedSubstitution,
+    TrailingBackslash,
+    /// Input ended where something else was expected, e.g. after `|` or `&&`
+    UnexpectedEof,
+    UnexpectedToken(String),
+    MissingRedirectionTarget,
+    UnsupportedRedirection(String),
+    /// The input ended before the delimiter line of a here-document
+    UnterminatedHereDoc(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+    /// 1-based line and column of the start of `span`
+    pub line: usize,
+    pub column: usize,
+    source_line: String,
+}
+
+impl ParseError {
+    fn new(kind: ParseErrorKind, span: Span, source: &str) -> ParseError {
+        let before = &source[..span.start];
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = source[line_start..]
+            .find('\n')
+            .map(|i| i + line_start)
+            .unwrap_or_else(|| source.len());
+
+        ParseError {
+            kind,
+            span,
+            line: before.matches('\n').count() + 1,
+            column: source[line_start..span.start].chars().count() + 1,
+            source_line: source[line_start..line_end].to_owned(),
+        }
+    }
+
+    /// If the input is valid so far, but needs more lines to be complete (e.g. an open quote)
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self.kind,
+            ParseErrorKind::UnterminatedQuote(_)
+                | ParseErrorKind::UnterminatedSubstitution
+                | ParseErrorKind::TrailingBackslash
+                | ParseErrorKind::UnexpectedEof
+                | ParseErrorKind::UnterminatedHereDoc(_)
+        )
+    }
+}
+
+impl error::Error for ParseError {}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match &self.kind {
+            ParseErrorKind::UnterminatedQuote(q) => format!("unterminated quote `{}`", q),
+            ParseErrorKind::UnterminatedSubstitution => {
+                "unterminated command substitution `$(`".to_owned()
+            }
+            ParseErrorKind::TrailingBackslash => "unexpected end of input after `\\`".to_owned(),
+            ParseErrorKind::UnexpectedEof => "unexpected end of input".to_owned(),
+            ParseErrorKind::UnexpectedToken(t) => format!("unexpected token `{}`", t),
+            ParseErrorKind::MissingRedirectionTarget => "missing redirection target".to_owned(),
+            ParseErrorKind::UnsupportedRedirection(r) => format!("unsupported redirection `{}`", r),
+            ParseErrorKind::UnterminatedHereDoc(d) => {
+                format!("here-document not terminated by `{}`", d)
+            }
+        };
+        writeln!(
+            f,
+            "Parse error at line {}, column {} - {}",
+            self.line, self.column, msg
+        )?;
+        writeln!(f, "{}", self.source_line)?;
+        write!(f, "{:>width$}", "^", width = self.column)
+    }
+}
+
+/// Characters that end a word when not quoted
+fn is_metachar(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '|' | '&' | ';' | '<' | '>')
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str) -> Lexer<'a> {
+        Lexer {
+            source,
+            chars: source.char_indices().peekable(),
+        }
+    }
+
+    fn error(&self, kind: ParseErrorKind, span: Span) -> ParseError {
+        ParseError::new(kind, span, self.source)
+    }
+
+    /// Byte offset of the next character
+    fn offset(&mut self) -> usize {
+        self.chars
+            .peek()
+            .map(|(i, _)| *i)
+            .unwrap_or_else(|| self.source.len())
+    }
+
+    /// Consumes the next character if it is `c`
+    fn eat(&mut self, c: char) -> bool {
+        if matches!(self.chars.peek(), Some((_, next)) if *next == c) {
+            self.chars.next();
+            return true;
+        }
+        false
+    }
+
+    fn token(&mut self, kind: TokenKind, start: usize) -> Token {
+        Token {
+            kind,
+            span: Span::new(start, self.offset()),
+        }
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut tokens = vec![];
+        //Index of the delimiter of each here-document whose body hasn't been read yet
+        let mut here_docs = vec![];
+
+        while let Some(&(start, c)) = self.chars.peek() {
+            let count = tokens.len();
+            match c {
+                '\n' => {
+                    self.chars.next();
+                    tokens.push(self.token(TokenKind::Newline, start));
+                    self.here_doc_bodies(&mut tokens, &mut here_docs)?;
+                }
+                _ if c.is_whitespace() => {
+                    self.chars.next();
+                }
+                //Comments run until the end of the line
+                '#' => {
+                    while matches!(self.chars.peek(), Some((_, c)) if *c != '\n') {
+                        self.chars.next();
+                    }
+                }
+                '|' => {
+                    self.chars.next();
+                    let kind = if self.eat('|') {
+                        TokenKind::Or
+                    } else if self.eat('&') {
+                        TokenKind::Pipe(Pipe::Err)
+                    } else {
+                        TokenKind::Pipe(Pipe::Std)
+                    };
+                    tokens.push(self.token(kind, start));
+                }
+                '&' => {
+                    self.chars.next();
+                    let kind = if self.eat('&') {
+                        TokenKind::And
+                    } else if self.eat('>') {
+                        let op = if self.eat('>') { "&>>" } else { "&>" };
+                        self.redirection(op, start)?
+                    } else {
+                        TokenKind::Ampersand
+                    };
+                    tokens.push(self.token(kind, start));
+                }
+                ';' => {
+                    self.chars.next();
+                    tokens.push(self.token(TokenKind::Semicolon, start));
+                }
+                '<' | '>' => {
+                    let kind = self.redirection_operator(String::new(), start)?;
+                    tokens.push(self.token(kind, start));
+                }
+                _ => {
+                    let kind = self.word(start)?;
+                    tokens.push(self.token(kind, start));
+                }
+            }
+
+            if tokens.len() > count
+                && matches!(
+                    tokens[count].kind,
+                    TokenKind::Redirection(Redirection::HereDoc)
+                        | TokenKind::Redirection(Redirection::HereDocStripTabs)
+                )
+            {
+                here_docs.push(count + 1);
+            }
+        }
+
+        //`cat <<EOF` alone still needs its body
+        match here_docs.first().and_then(|&i| tokens.get(i)) {
+            Some(Token {
+                kind: TokenKind::Word(delimiter),
+                span,
+            }) => Err(self.error(
+                ParseErrorKind::UnterminatedHereDoc(unquote(delimiter)),
+                *span,
+            )),
+            _ => Ok(tokens),
+        }
+    }
+
+    /// Reads the bodies of the pending `here_docs`, from the lines following a new line, one after the other.
+    /// Each body is inserted right after its delimiter
+    fn here_doc_bodies(
+        &mut self,
+        tokens: &mut Vec<Token>,
+        here_docs: &mut Vec<usize>,
+    ) -> Result<(), ParseError> {
+        let mut bodies = vec![];
+        for i in here_docs.drain(..) {
+            //Without a delimiter, the parser reports the missing target
+            let (delimiter, span) = match &tokens[i] {
+                Token {
+                    kind: TokenKind::Word(delimiter),
+                    span,
+                } => (delimiter.clone(), *span),
+                _ => continue,
+            };
+            let strip_tabs =
+                tokens[i - 1].kind == TokenKind::Redirection(Redirection::HereDocStripTabs);
+
+            let start = self.offset();
+            let body = self
+                .here_doc_body(&unquote(&delimiter), strip_tabs)
+                .ok_or_else(|| {
+                    self.error(
+                        ParseErrorKind::UnterminatedHereDoc(unquote(&delimiter)),
+                        span,
+                    )
+                })?;
+            let here_doc = HereDoc {
+                body,
+                quoted: delimiter.contains(['\'', '"', '\\']),
+            };
+            bodies.push((i + 1, self.token(TokenKind::HereDoc(here_doc), start)));
+        }
+
+        //From the last one, so the indexes of the others stay valid
+        for (i, body) in bodies.into_iter().rev() {
+            tokens.insert(i, body);
+        }
+        Ok(())
+    }
+
+    /// Lines up to the one that is exactly `delimiter`, which is consumed but not included. `None` if the input ends
+    /// before it
+    fn here_doc_body(&mut self, delimiter: &str, strip_tabs: bool) -> Option<String> {
+        let mut body = String::new();
+        loop {
+            self.chars.peek()?;
+            let mut line = String::new();
+            for (_, c) in self.chars.by_ref() {
+                if c == '\n' {
+                    break;
+                }
+                line.push(c);
+            }
+
+            let line = if strip_tabs {
+                line.trim_start_matches('\t')
+            } else {
+                &line
+            };
+            if line == delimiter {
+                return Some(body);
+            }
+            body.push_str(line);
+            body.push('\n');
+        }
+    }
+
+    fn redirection(&self, op: &str, start: usize) -> Result<TokenKind, ParseError> {
+        Redirection::from_str(op)
+            .map(TokenKind::Redirection)
+            .map_err(|_| {
+                self.error(
+                    ParseErrorKind::UnsupportedRedirection(op.to_owned()),
+                    Span::new(start, start + op.len()),
+                )
+            })
+    }
+
+    /// Reads a redirection operator starting at `<` or `>`, `op` holds the file descriptor number, if any
+    fn redirection_operator(
+        &mut self,
+        mut op: String,
+        start: usize,
+    ) -> Result<TokenKind, ParseError> {
+        match self.chars.next() {
+            Some((_, '<')) => {
+                op.push('<');
+                if self.eat('<') {
+                    op.push('<');
+                    if self.eat('<') {
+                        op.push('<');
+                    } else if self.eat('-') {
+                        op.push('-');
+                    }
+                } else if self.eat('&') {
+                    op.push('&');
+                } else if self.eat('>') {
+                    op.push('>');
+                }
+            }
+            //The target of a duplication, like the `1` of `2>&1`, is a word of its own
+            Some((_, '>')) => {
+                op.push('>');
+                if self.eat('>') {
+                    op.push('>');
+                } else if self.eat('&') {
+                    op.push('&');
+                } else if self.eat('|') {
+                    op.push('|');
+                }
+            }
+            _ => unreachable!("redirection_operator called without < or >"),
+        }
+        self.redirection(&op, start)
+    }
+
+    /// Pushes the characters into `word` up to the byte offset `end`, included
+    fn push_until(&mut self, word: &mut String, end: usize) {
+        while let Some((_, c)) = self.chars.next_if(|(i, _)| *i <= end) {
+            word.push(c);
+        }
+    }
+
+    /// Reads a `$(...)` into `word`, `start` being the offset of its `$`. The `$` itself may have been read already
+    fn command_substitution(&mut self, word: &mut String, start: usize) -> Result<(), ParseError> {
+        let end = closing_paren(&self.source[start + 2..])
+            .map(|end| start + 2 + end)
+            .ok_or_else(|| {
+                self.error(
+                    ParseErrorKind::UnterminatedSubstitution,
+                    Span::new(start, start + 2),
+                )
+            })?;
+        self.push_until(word, end);
+        Ok(())
+    }
+
+    fn word(&mut self, start: usize) -> Result<TokenKind, ParseError> {
+        let mut word = String::new();
+        let mut quoted = false;
+
+        while let Some(&(i, c)) = self.chars.peek() {
+            match c {
+                //A number immediately followed by `<` or `>` is the file descriptor of a redirection
+                '<' | '>'
+                    if !quoted && !word.is_empty() && word.chars().all(|c| c.is_ascii_digit()) =>
+                {
+                    return self.redirection_operator(word, start);
+                }
+                _ if is_metachar(c) => break,
+                //`${...}` is part of the word, even with spaces or operators inside, e.g. `${A:-a b}`
+                '$' if self.source[i..].starts_with("${") => {
+                    let end = closing_brace(&self.source[i + 2..]).map(|end| i + 2 + end);
+                    self.chars.next();
+                    word.push(c);
+                    if let Some(end) = end {
+                        self.push_until(&mut word, end);
+                    }
+                }
+                //So is `$(...)`, which needs its closing `)` like a quote
+                '$' if self.source[i..].starts_with("$(") => {
+                    self.command_substitution(&mut word, i)?;
+                }
+                '`' => {
+                    self.chars.next();
+                    word.push(c);
+                    loop {
+                        match self.chars.next() {
+                            Some((_, '`')) => break,
+                            Some((_, '\\')) => {
+                                word.push('\\');
+                                word.extend(self.chars.next().map(|(_, c)| c));
+                            }
+                            Some((_, c)) => word.push(c),
+                            None => {
+                                return Err(self.error(
+                                    ParseErrorKind::UnterminatedQuote('`'),
+                                    Span::new(i, i + 1),
+                                ))
+                            }
+                        }
+                    }
+                    word.push('`');
+                }
+                '\'' => {
+                    quoted = true;
+                    self.chars.next();
+                    word.push(c);
+                    loop {
+                        match self.chars.next() {
+                            Some((_, '\'')) => break,
+                            Some((_, c)) => word.push(c),
+                            None => {
+                                return Err(self.error(
+                                    ParseErrorKind::UnterminatedQuote('\''),
+                                    Span::new(i, i + 1),
+                                ))
+                            }
+                        }
+                    }
+                    word.push('\'');
+                }
+                '"' => {
+                    quoted = true;
+                    self.chars.next();
+                    word.push(c);
+                    loop {
+                        match self.chars.next() {
+                            Some((_, '"')) => break,
+                            //Line continuation, both the `\` and the new line are removed
+                            Some((_, '\\')) if self.eat('\n') => {}
+                            Some((_, '\\')) => {
+                                word.push('\\');
+                                if let Some((_, c)) = self.chars.next() {
+                                    word.push(c);
+                                }
+                            }
+                            Some((j, '$')) if self.source[j..].starts_with("$(") => {
+                                word.push('$');
+                                self.command_substitution(&mut word, j)?;
+                            }
+                            Some((_, c)) => word.push(c),
+                            None => {
+                                return Err(self.error(
+                                    ParseErrorKind::UnterminatedQuote('"'),
+                                    Span::new(i, i + 1),
+                                ))
+                            }
+                        }
+                    }
+                    word.push('"');
+                }
+                '\\' => {
+                    quoted = true;
+                    self.chars.next();
+                    match self.chars.next() {
+                        Some((_, '\n')) => {}
+                        Some((_, c)) => {
+                            word.push('\\');
+                            word.push(c);
+                        }
+                        None => {
+                            return Err(
+                                self.error(ParseErrorKind::TrailingBackslash, Span::new(i, i + 1))
+                            )
+                        }
+                    }
+                }
+                _ => {
+                    self.chars.next();
+                    word.push(c);
+                }
+            }
+        }
+
+        Ok(TokenKind::Word(word))
+    }
+}
+
+/// Delimiter of a here-document, as it has to appear on its last line: without its quotes and escapes
+fn unquote(word: &str) -> String {
+    let mut unquoted = String::with_capacity(word.len());
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next()),
+            '\'' => unquoted.extend(chars.by_ref().take_while(|c| *c != '\'')),
+            '"' => {}
+            _ => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+/// Byte offset of the `}` closing a `${`, in `s` starting right after it. Quotes, escapes and nested braces are
+/// skipped. `None` if it isn't closed
+pub fn closing_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut double_quoted = false;
+    let mut chars = s.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '"' => double_quoted = !double_quoted,
+            '\'' if !double_quoted => {
+                chars.find(|(_, c)| *c == '\'')?;
+            }
+            '{' if !double_quoted => depth += 1,
+            '}' if !double_quoted && depth == 0 => return Some(i),
+            '}' if !double_quoted => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Byte offset of the `)` closing a `$(`, in `s` starting right after it. Quotes, escapes and nested parentheses
+/// are skipped. `None` if it isn't closed
+pub fn closing_paren(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut chars = s.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '\'' => {
+                chars.find(|(_, c)| *c == '\'')?;
+            }
+            '"' | '`' => loop {
+                match chars.next()? {
+                    (_, '\\') => {
+                        chars.next();
+                    }
+                    (_, q) if q == c => break,
+                    _ => {}
+                }
+            },
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits a word like `NAME=value` into an Assignment. The name can't be quoted
+fn assignment(raw: &str, span: Span) -> Option<Assignment> {
+    let (name, value) = raw.split_once('=')?;
+    if !state::is_name(name) {
+        return None;
+    }
+    let value_start = span.start + name.len() + 1;
+    Some(Assignment {
+        name: name.to_owned(),
+        value: Word {
+            raw: value.to_owned(),
+            span: Span::new(value_start, span.end),
+        },
+        span,
+    })
+}
+
+/// Splits the input into words and operators
+pub fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    Lexer::new(source).tokenize()
+}
+
+/// Parses the input into a `List`
+pub fn parse(source: &str) -> Result<List, ParseError> {
+    Parser::new(source)?.parse_list()
+}
+
+pub struct Parser<'a> {
+    source: &'a str,
+    tokens: Peekable<std::vec::IntoIter<Token>>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(source: &'a str) -> Result<Parser<'a>, ParseError> {
+        Ok(Parser {
+            source,
+            tokens: tokenize(source)?.into_iter().peekable(),
+        })
+    }
+
+    fn peek_kind(&mut self) -> Option<&TokenKind> {
+        self.tokens.peek().map(|t| &t.kind)
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek_kind() == Some(&TokenKind::Newline) {
+            self.tokens.next();
+        }
+    }
+
+    fn eof_span(&self) -> Span {
+        Span::new(self.source.len(), self.source.len())
+    }
+
+    fn unexpected(&self, token: &Token) -> ParseError {
+        let text = match token.kind {
+            TokenKind::Newline => "newline",
+            _ => &self.source[token.span.start..token.span.end],
+        };
+        ParseError::new(
+            ParseErrorKind::UnexpectedToken(text.to_owned()),
+            token.span,
+            self.source,
+        )
+    }
+
+    /// Error for the next token, or for the end of the input
+    fn unexpected_next(&mut self) -> ParseError {
+        match self.tokens.next() {
+            Some(token) => self.unexpected(&token),
+            None => ParseError::new(ParseErrorKind::UnexpectedEof, self.eof_span(), self.source),
+        }
+    }
+
+    /// <LIST> ::= <SUBLIST> ; <LIST> | <SUBLIST> & <LIST> | <SUBLIST>
+    pub fn parse_list(&mut self) -> Result<List, ParseError> {
+        let mut items = vec![];
+
+        loop {
+            self.skip_newlines();
+            match self.peek_kind() {
+                None => break,
+                Some(TokenKind::Word(_)) | Some(TokenKind::Redirection(_)) => {}
+                Some(_) => return Err(self.unexpected_next()),
+            }
+
+            let sublist = self.parse_sublist()?;
+
+            let terminator = match self.tokens.next() {
+                None => Terminator::Sequential,
+                Some(t) => match t.kind {
+                    TokenKind::Semicolon | TokenKind::Newline => Terminator::Sequential,
+                    TokenKind::Ampersand => Terminator::Background,
+                    _ => return Err(self.unexpected(&t)),
+                },
+            };
+
+            items.push(ListItem {
+                sublist,
+                terminator,
+            });
+        }
+
+        Ok(List { items })
+    }
+
+    /// <SUBLIST> ::= <PIPELINE> && <SUBLIST> | <PIPELINE> || <SUBLIST> | <PIPELINE>
+    fn parse_sublist(&mut self) -> Result<Sublist, ParseError> {
+        let first = self.parse_pipeline()?;
+        let mut span = first.span;
+        let mut rest = vec![];
+
+        loop {
+            let connector = match self.peek_kind() {
+                Some(TokenKind::And) => Connector::And,
+                Some(TokenKind::Or) => Connector::Or,
+                _ => break,
+            };
+            self.tokens.next();
+            self.skip_newlines();
+
+            let pipeline = self.parse_pipeline()?;
+            span = span.to(pipeline.span);
+            rest.push((connector, pipeline));
+        }
+
+        Ok(Sublist { first, rest, span })
+    }
+
+    /// <PIPELINE> ::= <STEP> | <PIPELINE> | <STEP> |& <PIPELINE> | <STEP>
+    fn parse_pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let first = self.parse_step()?;
+        let mut span = first.span;
+        let mut steps = vec![first];
+        let mut pipes = vec![];
+
+        while let Some(TokenKind::Pipe(_)) = self.peek_kind() {
+            if let Some(Token {
+                kind: TokenKind::Pipe(pipe),
+                ..
+            }) = self.tokens.next()
+            {
+                pipes.push(pipe);
+            }
+            self.skip_newlines();
+
+            let step = self.parse_step()?;
+            span = span.to(step.span);
+            steps.push(step);
+        }
+
+        Ok(Pipeline { steps, pipes, span })
+    }
+
+    /// <STEP> ::= [NAME=value...] [Collection of words and redirections]
+    /// Redirections can be anywhere between the words, `cmd >out arg` has the argument `arg`
+    fn parse_step(&mut self) -> Result<Step, ParseError> {
+        let mut assignments = vec![];
+        let mut words = vec![];
+        let mut redirections = vec![];
+        let mut span: Option<Span> = None;
+
+        loop {
+            match self.peek_kind() {
+                Some(TokenKind::Word(_)) => {
+                    if let Some(Token {
+                        kind: TokenKind::Word(raw),
+                        span: word_span,
+                    }) = self.tokens.next()
+                    {
+                        span = Some(span.map_or(word_span, |s| s.to(word_span)));
+                        match assignment(&raw, word_span) {
+                            //Only before the first word, `echo A=1` has an argument
+                            Some(a) if words.is_empty() => assignments.push(a),
+                            _ => words.push(Word {
+                                raw,
+                                span: word_span,
+                            }),
+                        }
+                    }
+                }
+                Some(TokenKind::Redirection(_)) => {
+                    let redirect = self.parse_redirect()?;
+                    span = Some(span.map_or(redirect.span, |s| s.to(redirect.span)));
+                    redirections.push(redirect);
+                }
+                _ => break,
+            }
+        }
+
+        match span {
+            Some(span) => Ok(Step {
+                assignments,
+                words,
+                redirections,
+                span,
+            }),
+            None => Err(self.unexpected_next()),
+        }
+    }
+
+    fn parse_redirect(&mut self) -> Result<Redirect, ParseError> {
+        let (kind, op_span) = match self.tokens.next() {
+            Some(Token {
+                kind: TokenKind::Redirection(kind),
+                span,
+            }) => (kind, span),
+            _ => unreachable!("parse_redirect called without a redirection token"),
+        };
+
+        match self.tokens.next() {
+            Some(Token {
+                kind: TokenKind::Word(raw),
+                span,
+            }) => {
+                let here_doc = match self.peek_kind() {
+                    Some(TokenKind::HereDoc(_)) => match self.tokens.next() {
+                        Some(Token {
+                            kind: TokenKind::HereDoc(here_doc),
+                            ..
+                        }) => Some(here_doc),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                Ok(Redirect {
+                    kind,
+                    target: Word { raw, span },
+                    span: op_span.to(span),
+                    here_doc,
+                })
+            }
+            Some(t) if t.kind != TokenKind::Newline => Err(self.unexpected(&t)),
+            _ => Err(ParseError::new(
+                ParseErrorKind::MissingRedirectionTarget,
+                op_span,
+                self.source,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn words(list: &List) -> Vec<Vec<Vec<&str>>> {
+        list.items
+            .iter()
+            .map(|item| {
+                std::iter::once(&item.sublist.first)
+                    .chain(item.sublist.rest.iter().map(|(_, p)| p))
+                    .map(|p| {
+                        p.steps
+                            .iter()
+                            .flat_map(|s| s.words.iter().map(|w| w.raw.as_str()))
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_operators() {
+        let tokens: Vec<TokenKind> = tokenize("a|b |& c&&d || e; f &")
+            .unwrap()
+            .into_iter()
+            .map(|t| t.kind)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                TokenKind::Word("a".to_owned()),
+                TokenKind::Pipe(Pipe::Std),
+                TokenKind::Word("b".to_owned()),
+                TokenKind::Pipe(Pipe::Err),
+                TokenKind::Word("c".to_owned()),
+                TokenKind::And,
+                TokenKind::Word("d".to_owned()),
+                TokenKind::Or,
+                TokenKind::Word("e".to_owned()),
+                TokenKind::Semicolon,
+                TokenKind::Word("f".to_owned()),
+                TokenKind::Ampersand,
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_quotes_keep_operators() {
+        let tokens = tokenize("echo 'a | b' \"c && d\" e\\;f").unwrap();
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(tokens[1].kind, TokenKind::Word("'a | b'".to_owned()));
+        assert_eq!(tokens[2].kind, TokenKind::Word("\"c && d\"".to_owned()));
+        assert_eq!(tokens[3].kind, TokenKind::Word("e\\;f".to_owned()));
+        assert_eq!(tokens[1].span, Span::new(5, 12));
+    }
+
+    #[test]
+    fn tokenize_redirections() {
+        let tokens: Vec<TokenKind> =
+            tokenize("cmd<in >out 2>>err &>all 2>&1 x 3<>rw 4<&- 5>&1 >|f")
+                .unwrap()
+                .into_iter()
+                .map(|t| t.kind)
+                .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                TokenKind::Word("cmd".to_owned()),
+                TokenKind::Redirection(Redirection::ReadIn(0)),
+                TokenKind::Word("in".to_owned()),
+                TokenKind::Redirection(Redirection::WriteOut(1)),
+                TokenKind::Word("out".to_owned()),
+                TokenKind::Redirection(Redirection::AppendOut(2)),
+                TokenKind::Word("err".to_owned()),
+                TokenKind::Redirection(Redirection::WriteOutErr),
+                TokenKind::Word("all".to_owned()),
+                TokenKind::Redirection(Redirection::DuplicateOut(2)),
+                TokenKind::Word("1".to_owned()),
+                TokenKind::Word("x".to_owned()),
+                TokenKind::Redirection(Redirection::ReadWrite(3)),
+                TokenKind::Word("rw".to_owned()),
+                TokenKind::Redirection(Redirection::DuplicateIn(4)),
+                TokenKind::Word("-".to_owned()),
+                TokenKind::Redirection(Redirection::DuplicateOut(5)),
+                TokenKind::Word("1".to_owned()),
+                TokenKind::Redirection(Redirection::Clobber(1)),
+                TokenKind::Word("f".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_line_continuation_and_comment() {
+        let tokens = tokenize("ec\\\nho a # a comment").unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].kind, TokenKind::Word("echo".to_owned()));
+    }
+
+    #[test]
+    fn parse_list_sublists() {
+        let list = parse("make && ./run || echo failed; date").unwrap();
+        assert_eq!(list.items.len(), 2);
+        assert_eq!(
+            list.items[0]
+                .sublist
+                .rest
+                .iter()
+                .map(|(c, _)| *c)
+                .collect::<Vec<_>>(),
+            vec![Connector::And, Connector::Or]
+        );
+        assert_eq!(
+            words(&list),
+            vec![
+                vec![vec!["make"], vec!["./run"], vec!["echo", "failed"]],
+                vec![vec!["date"]]
+            ]
+        );
+        assert_eq!(list.items[0].sublist.span, Span::new(0, 28));
+    }
+
+    #[test]
+    fn parse_background_terminator() {
+        let list = parse("sleep 1 & echo a\necho b").unwrap();
+        let terminators: Vec<Terminator> = list.items.iter().map(|i| i.terminator).collect();
+        assert_eq!(
+            terminators,
+            vec![
+                Terminator::Background,
+                Terminator::Sequential,
+                Terminator::Sequential
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_pipeline_redirections() {
+        let list = parse("wc -c < tests/lorem | cat > tests/output arg").unwrap();
+        let pipeline = &list.items[0].sublist.first;
+        assert_eq!(pipeline.pipes, vec![Pipe::Std]);
+        let redirections = &pipeline.steps[0].redirections;
+        assert_eq!(redirections.len(), 1);
+        assert_eq!(redirections[0].kind, Redirection::ReadIn(0));
+        let redirections = &pipeline.steps[1].redirections;
+        assert_eq!(redirections.len(), 1);
+        assert_eq!(redirections[0].target.raw, "tests/output");
+        assert_eq!(words(&list), vec![vec![vec!["wc", "-c", "cat", "arg"]]]);
+    }
+
+    #[test]
+    fn parse_here_documents() {
+        let list =
+            parse("cat <<EOF | tr a b <<-'X'; echo c\nline $A\nEOF\n\tx\n\tX\necho d").unwrap();
+        let pipeline = &list.items[0].sublist.first;
+        let redirections: Vec<_> = pipeline
+            .steps
+            .iter()
+            .flat_map(|s| &s.redirections)
+            .collect();
+        let kinds: Vec<_> = redirections.iter().map(|r| r.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![Redirection::HereDoc, Redirection::HereDocStripTabs]
+        );
+        assert_eq!(
+            redirections[0].here_doc,
+            Some(HereDoc {
+                body: "line $A\n".to_owned(),
+                quoted: false
+            })
+        );
+        assert_eq!(
+            redirections[1].here_doc,
+            Some(HereDoc {
+                body: "x\n".to_owned(),
+                quoted: true
+            })
+        );
+        assert_eq!(
+            words(&list),
+            vec![
+                vec![vec!["cat", "tr", "a", "b"]],
+                vec![vec!["echo", "c"]],
+                vec![vec!["echo", "d"]]
+            ]
+        );
+
+        let list = parse("cat <<< 'a b'").unwrap();
+        let redirect = &list.items[0].sublist.first.steps[0].redirections[0];
+        assert_eq!(redirect.kind, Redirection::HereString);
+        assert_eq!(redirect.target.raw, "'a b'");
+        assert_eq!(redirect.here_doc, None);
+
+        assert_eq!(
+            parse("cat <<EOF\nabc").unwrap_err().kind,
+            ParseErrorKind::UnterminatedHereDoc("EOF".to_owned())
+        );
+        assert!(parse("cat <<\\EOF").unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn parse_error_column() {
+        let e = parse("echo a && && b").unwrap_err();
+        assert_eq!(e.kind, ParseErrorKind::UnexpectedToken("&&".to_owned()));
+        assert_eq!((e.line, e.column), (1, 11));
+        assert!(!e.is_incomplete());
+
+        let e = parse("echo a\n; ls").unwrap_err();
+        assert_eq!((e.line, e.column), (2, 1));
+
+        let e = parse("ls >").unwrap_err();
+        assert_eq!(e.kind, ParseErrorKind::MissingRedirectionTarget);
+        assert_eq!(e.column, 4);
+    }
+
+    #[test]
+    fn parse_assignments() {
+        let list = parse("A=1 B='x y' cmd C=2; D=").unwrap();
+        let step = &list.items[0].sublist.first.steps[0];
+        let assignments: Vec<(&str, &str)> = step
+            .assignments
+            .iter()
+            .map(|a| (a.name.as_str(), a.value.raw.as_str()))
+            .collect();
+        assert_eq!(assignments, vec![("A", "1"), ("B", "'x y'")]);
+        assert_eq!(step.assignments[1].value.span, Span::new(6, 11));
+        assert_eq!(words(&list), vec![vec![vec!["cmd", "C=2"]], vec![vec![]]]);
+
+        let step = &list.items[1].sublist.first.steps[0];
+        assert_eq!(step.assignments[0].name, "D");
+        assert_eq!(step.assignments[0].value.raw, "");
+
+        //Not valid names
+        let list = parse("1A=1 'B'=2").unwrap();
+        assert!(list.items[0].sublist.first.steps[0].assignments.is_empty());
+    }
+
+    #[test]
+    fn tokenize_braced_parameters() {
+        let tokens = tokenize("echo ${A:-a b}c ${B;x").unwrap();
+        assert_eq!(tokens[1].kind, TokenKind::Word("${A:-a b}c".to_owned()));
+        //Unmatched braces are left to the expansion
+        assert_eq!(tokens[2].kind, TokenKind::Word("${B".to_owned()));
+        assert_eq!(tokens[3].kind, TokenKind::Semicolon);
+
+        assert_eq!(closing_brace("A:-\"}\"}"), Some(6));
+        assert_eq!(closing_brace("A:-${B}}x"), Some(7));
+        assert_eq!(closing_brace("A:-'}'"), None);
+    }
+
+    #[test]
+    fn tokenize_command_substitutions() {
+        let tokens = tokenize("echo $(ls -a | wc -l)x \"$(echo \"a b\")\" `echo c d`").unwrap();
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(
+            tokens[1].kind,
+            TokenKind::Word("$(ls -a | wc -l)x".to_owned())
+        );
+        assert_eq!(
+            tokens[2].kind,
+            TokenKind::Word("\"$(echo \"a b\")\"".to_owned())
+        );
+        assert_eq!(tokens[3].kind, TokenKind::Word("`echo c d`".to_owned()));
+
+        assert_eq!(closing_paren("a $(b) ')' \\) \")\")c"), Some(17));
+        assert!(parse("echo $(ls").unwrap_err().is_incomplete());
+        assert!(parse("echo `ls").unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn parse_incomplete() {
+        assert!(parse("echo 'abc").unwrap_err().is_incomplete());
+        assert!(parse("echo \"abc").unwrap_err().is_incomplete());
+        assert!(parse("echo abc \\").unwrap_err().is_incomplete());
+        assert!(parse("echo abc |").unwrap_err().is_incomplete());
+        assert!(parse("true &&\n").unwrap_err().is_incomplete());
+        assert!(parse("true &&\n false").is_ok());
+    }
+
+    #[test]
+    fn parse_empty() {
+        assert!(parse("").unwrap().is_empty());
+        assert!(parse("  \n # only a comment").unwrap().is_empty());
+    }
+}
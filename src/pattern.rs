@@ -0,0 +1,235 @@
+//! Shell patterns, as used by pathname expansion (see `glob`), `${VAR%pattern}` and the other removal / replacement
+//! forms of parameter expansion.
+//!
+//! * `*` matches any string, including the empty one, `?` any single character
+//! * `[abc]`, `[a-z]` one of the characters in the brackets, `[!abc]` or `[^abc]` any character not in them.
+//!   Inside the brackets, `[:digit:]` and the other POSIX classes stand for all the characters of the class
+//! * `\` makes the next character match literally
+use std::ops::Range;
+
+/// Checks if `pattern` has any wildcard, i.e. if it can match something else than itself. A `[` without its `]` is
+/// an ordinary character
+pub fn has_wildcards(pattern: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let mut i = 0;
+    while i < pattern.len() {
+        match pattern[i] {
+            '*' | '?' => return true,
+            '[' if bracket(&pattern[i..], '\0').is_some() => return true,
+            '\\' => i += 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Checks if the whole `text` matches `pattern`
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches_chars(&pattern, &text)
+}
+
+fn matches_chars(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    //Where to resume after the last `*`, with it matching one more character
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match bracket(&pattern[p..], text[t]) {
+                Some((true, len)) => Some(len),
+                Some((false, _)) => None,
+                //Without a closing `]` it's an ordinary character
+                None if text[t] == '[' => Some(1),
+                None => None,
+            },
+            Some('\\') if p + 1 < pattern.len() => {
+                if pattern[p + 1] == text[t] {
+                    Some(2)
+                } else {
+                    None
+                }
+            }
+            Some(&c) if c == text[t] => Some(1),
+            _ => None,
+        };
+
+        match (step, backtrack) {
+            (Some(len), _) => {
+                p += len;
+                t += 1;
+            }
+            (None, Some((star, start))) => {
+                p = star + 1;
+                t = start + 1;
+                backtrack = Some((star, start + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Matches `c` against the bracket expression at the start of `pattern`.
+/// Returns if it matched and the length of the expression, or `None` if there's no closing `]`
+fn bracket(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let start = match pattern.get(i) {
+            None => return None,
+            //A `]` right after the opening is part of the set
+            Some(']') if !first => break,
+            Some('[') if pattern.get(i + 1) == Some(&':') => {
+                let name: String = pattern[i + 2..].iter().collect();
+                if let Some(end) = name.find(":]") {
+                    matched |= class_matches(&name[..end], c);
+                    i += 2 + name[..end].chars().count() + 2;
+                    first = false;
+                    continue;
+                }
+                '['
+            }
+            Some('\\') if i + 1 < pattern.len() => {
+                i += 1;
+                pattern[i]
+            }
+            Some(&start) => start,
+        };
+        first = false;
+
+        if pattern.get(i + 1) == Some(&'-') && !matches!(pattern.get(i + 2), None | Some(']')) {
+            let end = pattern[i + 2];
+            matched |= start <= c && c <= end;
+            i += 3;
+        } else {
+            matched |= start == c;
+            i += 1;
+        }
+    }
+
+    Some((matched != negated, i + 1))
+}
+
+/// Checks if `c` belongs to the POSIX character class `name`, e.g. `digit` for `[:digit:]`. Unknown classes match nothing
+fn class_matches(name: &str, c: char) -> bool {
+    match name {
+        "alnum" => c.is_alphanumeric(),
+        "alpha" => c.is_alphabetic(),
+        "ascii" => c.is_ascii(),
+        "blank" => c == ' ' || c == '\t',
+        "cntrl" => c.is_control(),
+        "digit" => c.is_ascii_digit(),
+        "graph" => !c.is_control() && !c.is_whitespace(),
+        "lower" => c.is_lowercase(),
+        "print" => !c.is_control(),
+        "punct" => c.is_ascii_punctuation(),
+        "space" => c.is_whitespace(),
+        "upper" => c.is_uppercase(),
+        "word" => c.is_alphanumeric() || c == '_',
+        "xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+/// Range of characters of `text` matched by `pattern` at the start, the shortest or the `longest` one
+pub fn match_prefix(pattern: &str, text: &str, longest: bool) -> Option<Range<usize>> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let mut ends: Box<dyn Iterator<Item = usize>> = if longest {
+        Box::new((0..=text.len()).rev())
+    } else {
+        Box::new(0..=text.len())
+    };
+    ends.find(|end| matches_chars(&pattern, &text[..*end]))
+        .map(|end| 0..end)
+}
+
+/// Range of characters of `text` matched by `pattern` at the end, the shortest or the `longest` one
+pub fn match_suffix(pattern: &str, text: &str, longest: bool) -> Option<Range<usize>> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let mut starts: Box<dyn Iterator<Item = usize>> = if longest {
+        Box::new(0..=text.len())
+    } else {
+        Box::new((0..=text.len()).rev())
+    };
+    starts
+        .find(|start| matches_chars(&pattern, &text[*start..]))
+        .map(|start| start..text.len())
+}
+
+/// Range of characters of the first, and longest, non-empty match of `pattern` in `text` from character `from` on
+pub fn find(pattern: &str, text: &str, from: usize) -> Option<Range<usize>> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    (from..text.len()).find_map(|start| {
+        (start + 1..=text.len())
+            .rev()
+            .find(|end| matches_chars(&pattern, &text[start..*end]))
+            .map(|end| start..end)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn match_patterns() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("a*b*c", "acb"));
+        assert!(matches("?[0-9][!a]", "x5b"));
+        assert!(!matches("?[0-9][!a]", "x5a"));
+        assert!(matches("[]x]", "]"));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("[a", "[a"));
+        assert!(matches("", ""));
+        assert!(!matches("", "a"));
+    }
+
+    #[test]
+    fn match_classes() {
+        assert!(matches("[[:digit:]]*", "1.txt"));
+        assert!(!matches("[[:digit:]]*", "a.txt"));
+        assert!(matches("[![:upper:][:space:]]", "a"));
+        assert!(!matches("[![:upper:][:space:]]", "A"));
+        assert!(matches("[[:alpha:]_-]", "-"));
+        assert!(!matches("[[:nope:]]", "a"));
+
+        assert!(has_wildcards("a*"));
+        assert!(has_wildcards("[ab]"));
+        assert!(!has_wildcards("["));
+        assert!(!has_wildcards("a\\*"));
+        assert!(!has_wildcards("plain"));
+    }
+
+    #[test]
+    fn match_parts() {
+        assert_eq!(match_prefix("*/", "a/b/c", false), Some(0..2));
+        assert_eq!(match_prefix("*/", "a/b/c", true), Some(0..4));
+        assert_eq!(match_suffix(".*", "a.tar.gz", false), Some(5..8));
+        assert_eq!(match_suffix(".*", "a.tar.gz", true), Some(1..8));
+        assert_eq!(match_suffix("x", "abc", true), None);
+        assert_eq!(find("b*", "abcb", 0), Some(1..4));
+        assert_eq!(find("b", "abcb", 2), Some(3..4));
+        assert_eq!(find("x", "abc", 0), None);
+    }
+}
@@ -1,196 +1,648 @@
-use crate::{redirection::Redirection, Step, StepOutput};
+use crate::{
+    job,
+    redirection::{FdTable, FdTarget, Opened, Redirection},
+    step::{self, Step, StepOutput},
+};
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, FcntlArg},
+    sys::wait::{waitpid, WaitPidFlag, WaitStatus},
+    unistd::{close, Pid},
+};
 use std::{
+    collections::BTreeMap,
     fmt,
     fs::File,
-    io::{prelude::*, Error, ErrorKind, Result, Stderr, Stdin, Stdout},
-    str::FromStr,
+    io::{self, prelude::*, Cursor, Error, ErrorKind, Result, Stderr, Stdin, Stdout},
+    net::TcpStream,
+    os::unix::io::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd},
+    process::Stdio,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
 };
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Pipe {
     Std,
     Err,
 }
 
 ///These combine additional traits, such as Debug, to the Readers used by the Pipeline
-pub trait PipelineReader: std::io::Read + std::fmt::Debug {}
-impl PipelineReader for File {}
-impl PipelineReader for Stdin {}
+pub trait PipelineReader: std::io::Read + std::fmt::Debug + Send {
+    /// A copy of the file descriptor backing the reader, if any, that processes can use directly.
+    /// Readers without one are copied into the Steps through a pipe
+    fn fd(&self) -> Option<OwnedFd> {
+        None
+    }
+}
+impl PipelineReader for File {
+    fn fd(&self) -> Option<OwnedFd> {
+        self.as_fd().try_clone_to_owned().ok()
+    }
+}
+impl PipelineReader for Stdin {
+    fn fd(&self) -> Option<OwnedFd> {
+        self.as_fd().try_clone_to_owned().ok()
+    }
+}
+impl PipelineReader for TcpStream {
+    fn fd(&self) -> Option<OwnedFd> {
+        self.as_fd().try_clone_to_owned().ok()
+    }
+}
+///Text already in memory, e.g. the body of a here-document
+impl PipelineReader for Cursor<Vec<u8>> {}
 
 ///These combine additional traits, such as Debug, to the Writers used by the Pipeline
 /// TODO: 2021-09-12 Change these two Enums?
-pub trait PipelineWriter: std::io::Write + std::fmt::Debug {}
-impl PipelineWriter for File {}
-impl PipelineWriter for Stdout {}
-impl PipelineWriter for Stderr {}
+pub trait PipelineWriter: std::io::Write + std::fmt::Debug + Send {
+    /// A copy of the file descriptor backing the writer, if any, that processes can use directly.
+    /// Output to writers without one is copied from a pipe
+    fn fd(&self) -> Option<OwnedFd> {
+        None
+    }
+}
+impl PipelineWriter for File {
+    fn fd(&self) -> Option<OwnedFd> {
+        self.as_fd().try_clone_to_owned().ok()
+    }
+}
+impl PipelineWriter for Stdout {
+    fn fd(&self) -> Option<OwnedFd> {
+        self.as_fd().try_clone_to_owned().ok()
+    }
+}
+impl PipelineWriter for Stderr {
+    fn fd(&self) -> Option<OwnedFd> {
+        self.as_fd().try_clone_to_owned().ok()
+    }
+}
+impl PipelineWriter for TcpStream {
+    fn fd(&self) -> Option<OwnedFd> {
+        self.as_fd().try_clone_to_owned().ok()
+    }
+}
 
-///A pipeline is composed by Steps (commands or builtins), and Pipes that connect the output from one Step to the next
-pub struct Pipeline {
-    steps: Vec<Step>,
-    pipes: Vec<Pipe>,
-    in_reader: Option<Box<dyn PipelineReader>>,
-    out_writer: Box<dyn PipelineWriter>,
-    err_writer: Box<dyn PipelineWriter>,
-    redirection_write_type: Option<Redirection>, //Used to change write logic
+/// In-memory writer whose contents can still be read after the Pipeline that wrote to it was consumed
+#[derive(Debug, Clone, Default)]
+pub struct OutputBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl OutputBuffer {
+    pub fn new() -> OutputBuffer {
+        OutputBuffer::default()
+    }
+
+    /// Takes everything written so far, leaving the buffer empty
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
 }
 
-impl fmt::Debug for Pipeline {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Pipeline")
-            .field("Steps", &self.steps)
-            .field("Pipes", &self.pipes)
-            .finish()
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
     }
 }
+impl PipelineWriter for OutputBuffer {}
+
+/// A file descriptor of a Step, once its pipes and redirections are resolved
+enum StepFd {
+    /// The shell's own one
+    Inherit,
+    Fd(OwnedFd),
+    Closed,
+}
 
-impl Pipeline {
-    /// TODO 2021-07-21 Currently the parsing implementation is naive, improve it.
-    /// CHANGE INPUT FOR A ITER OF STRINGS
-    pub fn new(words: Vec<String>) -> Result<Pipeline> {
-        let mut steps: Vec<Step> = vec![];
-        let mut pipes: Vec<Pipe> = vec![];
+impl StepFd {
+    fn try_clone(&self) -> Result<StepFd> {
+        Ok(match self {
+            StepFd::Inherit => StepFd::Inherit,
+            StepFd::Fd(fd) => StepFd::Fd(fd.try_clone()?),
+            StepFd::Closed => StepFd::Closed,
+        })
+    }
 
-        let mut in_reader: Option<Box<dyn PipelineReader>> = None;
-        let mut out_writer: Option<Box<dyn PipelineWriter>> = None;
-        let mut err_writer: Option<Box<dyn PipelineWriter>> = None;
+    fn stdio(self) -> Stdio {
+        match self {
+            StepFd::Fd(fd) => Stdio::from(fd),
+            //Closed fds are closed by the process itself, see `step::spawn`
+            StepFd::Inherit | StepFd::Closed => Stdio::inherit(),
+        }
+    }
 
-        let mut redirection_write_type = None;
-        let mut words_iter = words.into_iter();
+    /// Writes into a pipe (or file) whose reader may have already finished, which isn't an error for the writer.
+    /// Nothing is written into a closed fd
+    fn write_all(&self, buf: &[u8]) -> Result<()> {
+        let fd = match self {
+            StepFd::Fd(fd) if !buf.is_empty() => fd,
+            _ => return Ok(()),
+        };
+        match File::from(fd.try_clone()?).write_all(buf) {
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+            r => r,
+        }
+    }
+}
 
-        let mut next_step_temp_buffer: Vec<String> = vec![];
+/// Copy of the shell's own file descriptor `fd`
+fn dup(fd: RawFd) -> Result<OwnedFd> {
+    match fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(0)) {
+        // Safety: the new fd was just created, nothing else owns it
+        Ok(new) => Ok(unsafe { OwnedFd::from_raw_fd(new) }),
+        Err(_) => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{}: Bad file descriptor", fd),
+        )),
+    }
+}
 
-        while let Some(w) = words_iter.next() {
-            match w.as_str() {
-                _ if Redirection::is_redirection(&w) => {
-                    let redir = Redirection::from_str(&w).unwrap();
+/// File descriptor the Steps write into to reach `writer`. Writers without one get a pipe, which a thread copies
+/// into the writer
+fn sink(
+    mut writer: Box<dyn PipelineWriter>,
+    copy_threads: &mut Vec<JoinHandle<Result<u64>>>,
+) -> Result<OwnedFd> {
+    if let Some(fd) = writer.fd() {
+        return Ok(fd);
+    }
+    let (mut reader, pipe_writer) = io::pipe()?;
+    copy_threads.push(thread::spawn(move || io::copy(&mut reader, &mut writer)));
+    Ok(pipe_writer.into())
+}
 
-                    //Pipeline only needs the Type of redirection (so it knows which variable to set) and the corresponding
-                    //reader / writer
+/// File descriptor the Steps read from to get what's in `reader`. Readers without one get a pipe, which a thread
+/// copies the reader into
+fn source(
+    mut reader: Box<dyn PipelineReader>,
+    copy_threads: &mut Vec<JoinHandle<Result<u64>>>,
+) -> Result<OwnedFd> {
+    if let Some(fd) = reader.fd() {
+        return Ok(fd);
+    }
+    let (pipe_reader, mut pipe_writer) = io::pipe()?;
+    copy_threads.push(thread::spawn(move || {
+        //The Steps may finish without reading all of it
+        match io::copy(&mut reader, &mut pipe_writer) {
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(0),
+            r => r,
+        }
+    }));
+    Ok(pipe_reader.into())
+}
 
-                    let src_or_dst = words_iter.next();
-                    if src_or_dst.is_none() {
-                        return Err(Error::new(ErrorKind::InvalidInput, "Empty redirection"));
-                    }
-                    let src_or_dst = src_or_dst.unwrap();
-
-                    //We'll only be able to set the reader / writer (File, Socket, etc) DEPENDING on the redirection Type
-                    redir.configure_redirection(
-                        &src_or_dst,
-                        &mut in_reader,
-                        &mut out_writer,
-                        &mut err_writer,
-                    )?;
-
-                    if redir != Redirection::ReadIn {
-                        redirection_write_type = Some(redir);
-                    }
-                }
+/// Opens the file descriptors of the files opened by the redirections of `table`, see `FdTarget::Opened`
+fn open_fds(
+    table: &mut FdTable,
+    copy_threads: &mut Vec<JoinHandle<Result<u64>>>,
+) -> Result<Vec<OwnedFd>> {
+    table
+        .take_opened()
+        .into_iter()
+        .map(|opened| match opened {
+            Opened::Reader(reader) => source(reader, copy_threads),
+            Opened::Writer(writer) => sink(writer, copy_threads),
+            Opened::ReadWrite(fd) => Ok(fd),
+        })
+        .collect()
+}
 
-                // Step Delimitator
-                "|" => {
-                    pipes.push(Pipe::Std);
-                    steps.push(Step::new(next_step_temp_buffer.to_vec())?);
-                    next_step_temp_buffer.clear();
-                }
+/// Changes the file descriptors of a Step, `fds`, by the redirections of `table` for the fds where `applies`.
+/// `opened` holds the file descriptors of the files opened by the redirections, see `open_fds`
+fn redirect_fds(
+    mut fds: BTreeMap<RawFd, StepFd>,
+    table: &FdTable,
+    opened: &[OwnedFd],
+    applies: impl Fn(RawFd) -> bool,
+) -> Result<BTreeMap<RawFd, StepFd>> {
+    let mut redirected = BTreeMap::new();
+    for (fd, target) in table.fds().filter(|(fd, _)| applies(*fd)) {
+        let step_fd = match target {
+            //Duplications refer to the fds as they were before the redirections of `table`
+            FdTarget::Inherit(src) => match fds.get(&src) {
+                Some(step_fd) => step_fd.try_clone()?,
+                None => StepFd::Fd(dup(src)?),
+            },
+            FdTarget::Opened(i) => StepFd::Fd(opened[i].try_clone()?),
+            FdTarget::Closed => StepFd::Closed,
+        };
+        redirected.insert(fd, step_fd);
+    }
+    fds.extend(redirected);
+    Ok(fds)
+}
 
-                "|&" => {
-                    pipes.push(Pipe::Err);
-                    steps.push(Step::new(next_step_temp_buffer.to_vec())?);
-                    next_step_temp_buffer.clear();
-                }
+/// A Step started by `Pipeline::spawn`
+enum Running {
+    /// Process of a Command, or of a Builtin that isn't the last Step, and its output once it exited
+    Process(Pid, Option<StepOutput>),
+    /// Builtin that ran in the shell itself
+    Done(StepOutput),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PipelineState {
+    Running,
+    /// At least one of the processes was stopped, e.g. by Ctrl-Z
+    Stopped,
+    Done,
+}
 
-                _ => {
-                    next_step_temp_buffer.push(w);
+/// A Pipeline whose Steps were all started, see `Pipeline::spawn`
+pub struct RunningPipeline {
+    /// Process group shared by all the processes, when job control is enabled
+    pgid: Option<Pid>,
+    steps: Vec<Running>,
+    copy_threads: Vec<JoinHandle<Result<u64>>>,
+    /// First Step that couldn't be started, reported once the others finish
+    failure: Option<Error>,
+}
+
+impl fmt::Debug for RunningPipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RunningPipeline")
+            .field("pgid", &self.pgid)
+            .field("pids", &self.pids())
+            .finish()
+    }
+}
+
+impl RunningPipeline {
+    /// A single process started by `step::fork`, e.g. a Sublist running in the background
+    pub fn forked(pid: Pid) -> RunningPipeline {
+        RunningPipeline {
+            pgid: job::job_control().then_some(pid),
+            steps: vec![Running::Process(pid, None)],
+            copy_threads: vec![],
+            failure: None,
+        }
+    }
+
+    pub fn pgid(&self) -> Option<Pid> {
+        self.pgid
+    }
+
+    /// Pids of the processes of the Pipeline, in the order of the Steps
+    pub fn pids(&self) -> Vec<Pid> {
+        self.steps
+            .iter()
+            .filter_map(|s| match s {
+                Running::Process(pid, _) => Some(*pid),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Collects the state changes of the processes.
+    /// If `block`, waits until all of them finished or one of them was stopped
+    pub fn wait(&mut self, block: bool) -> Result<PipelineState> {
+        let mut flags = WaitPidFlag::WUNTRACED;
+        if !block {
+            flags |= WaitPidFlag::WNOHANG;
+        }
+
+        for step in self.steps.iter_mut() {
+            if let Running::Process(pid, output @ None) = step {
+                loop {
+                    match waitpid(*pid, Some(flags)) {
+                        Ok(WaitStatus::StillAlive) => return Ok(PipelineState::Running),
+                        Ok(WaitStatus::Stopped(..)) => return Ok(PipelineState::Stopped),
+                        Ok(status @ WaitStatus::Exited(..))
+                        | Ok(status @ WaitStatus::Signaled(..)) => {
+                            *output = Some(StepOutput::from(status));
+                            break;
+                        }
+                        Ok(_) | Err(Errno::EINTR) => {}
+                        Err(e) => return Err(e.into()),
+                    }
                 }
             }
         }
+        Ok(PipelineState::Done)
+    }
 
-        if !next_step_temp_buffer.is_empty() {
-            steps.push(Step::new(next_step_temp_buffer.to_vec())?);
+    /// Waits for every Step to finish, returning the `StepOutput` of the last one
+    pub fn finish(mut self) -> Result<StepOutput> {
+        while self.wait(true)? == PipelineState::Stopped {}
+
+        let mut last_out = None;
+        for step in std::mem::take(&mut self.steps) {
+            last_out = Some(match step {
+                Running::Process(_, output) => output.unwrap(),
+                Running::Done(out) => out,
+            });
         }
 
+        let RunningPipeline {
+            copy_threads,
+            failure,
+            ..
+        } = self;
+
+        //Every Step closed its end of the pipes, so the copy threads can finish
+        for handle in copy_threads {
+            handle.join().expect("Copy thread panicked")?;
+        }
+
+        match (failure, last_out) {
+            (Some(e), _) => Err(e),
+            (None, Some(out)) => Ok(out),
+            (None, None) => Err(Error::new(ErrorKind::InvalidInput, "No Steps on Pipeline")),
+        }
+    }
+}
+
+///A pipeline is composed by Steps (commands or builtins), and Pipes that connect the output from one Step to the next
+pub struct Pipeline {
+    steps: Vec<Step>,
+    pipes: Vec<Pipe>,
+    in_reader: Option<Box<dyn PipelineReader>>,
+    out_writer: Box<dyn PipelineWriter>,
+    err_writer: Box<dyn PipelineWriter>,
+    /// Applied on top of the reader and writers above: input of the first Step, output of the last one, errors and
+    /// other file descriptors of all of them
+    redirections: FdTable,
+    /// Redirections of each Step, applied after those of the whole Pipeline
+    step_redirections: Vec<FdTable>,
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("Steps", &self.steps)
+            .field("Pipes", &self.pipes)
+            .finish()
+    }
+}
+
+impl Pipeline {
+    /// Builds a Pipeline from Steps that were already parsed, and the redirections (with their source or destination)
+    /// that apply to the whole Pipeline
+    pub fn from_steps(
+        steps: Vec<Step>,
+        pipes: Vec<Pipe>,
+        redirections: Vec<(Redirection, String)>,
+    ) -> Result<Pipeline> {
         Ok(Pipeline {
             pipes,
             steps,
-            in_reader,
-            out_writer: out_writer.unwrap_or_else(|| Box::new(std::io::stdout())),
-            err_writer: err_writer.unwrap_or_else(|| Box::new(std::io::stderr())),
-            redirection_write_type,
+            in_reader: None,
+            out_writer: Box::new(std::io::stdout()),
+            err_writer: Box::new(std::io::stderr()),
+            redirections: FdTable::new(redirections)?,
+            step_redirections: vec![],
         })
     }
 
-    ///Executes all Steps, piping outputs/errors into inputs,
-    /// consuming the Pipeline and returning the `StepOutput` of the last step
-    pub fn run(mut self) -> Result<StepOutput> {
-        let mut pipeline_input = Vec::new();
-
-        //Read pipeline input from input source, if any
-        if let Some(mut src) = self.in_reader {
-            src.read_to_end(&mut pipeline_input)?;
+    /// Builds a Pipeline from Steps that were already parsed, each one with its own redirections
+    pub fn from_redirected_steps(
+        steps: Vec<(Step, Vec<(Redirection, String)>)>,
+        pipes: Vec<Pipe>,
+    ) -> Result<Pipeline> {
+        let mut step_redirections = Vec::with_capacity(steps.len());
+        let mut plain_steps = Vec::with_capacity(steps.len());
+        for (step, redirections) in steps {
+            step_redirections.push(FdTable::new(redirections)?);
+            plain_steps.push(step);
         }
 
-        let mut step_iter = self.steps.into_iter();
+        let mut pipeline = Pipeline::from_steps(plain_steps, pipes, vec![])?;
+        pipeline.step_redirections = step_redirections;
+        Ok(pipeline)
+    }
+
+    /// Replaces the destination of the Pipeline's output, e.g. to capture it in an `OutputBuffer`.
+    /// Output redirections still take precedence
+    pub fn out_writer(mut self, writer: Box<dyn PipelineWriter>) -> Pipeline {
+        self.out_writer = writer;
+        self
+    }
 
-        let curr_step = step_iter.next();
+    ///Executes all Steps concurrently, each Step's output (and errors, for `|&`) streamed into the next one's input
+    /// through a pipe, consuming the Pipeline and returning the `StepOutput` of the last step.
+    ///
+    /// The output of the last Step is written into the Pipeline's writers as it is produced, so the returned `StepOutput`
+    /// only holds what Builtins produced.
+    ///
+    /// The Pipeline runs in the foreground, if it gets stopped it's moved to the job table, see `job::wait_foreground`
+    pub fn run(self) -> Result<StepOutput> {
+        let command = self.to_string();
+        job::wait_foreground(command, self.spawn(true)?)
+    }
+
+    /// Whether the last Step is a Builtin, which `spawn` runs in the shell itself
+    pub fn ends_with_builtin(&self) -> bool {
+        matches!(self.steps.last(), Some(Step::Builtin(_)))
+    }
 
-        if curr_step.is_none() {
+    /// Starts every Step of the Pipeline, without waiting for them.
+    ///
+    /// With job control enabled all processes are put in the same process group, which is given the terminal when
+    /// in the `foreground`
+    pub fn spawn(self, foreground: bool) -> Result<RunningPipeline> {
+        if self.steps.is_empty() {
             return Err(Error::new(ErrorKind::InvalidInput, "No Steps on Pipeline"));
         }
 
-        let mut curr_step = curr_step.unwrap();
-
-        dbg!(&curr_step);
-        dbg!(String::from_utf8_lossy(&pipeline_input));
+        let Pipeline {
+            steps,
+            pipes,
+            in_reader,
+            out_writer,
+            err_writer,
+            mut redirections,
+            mut step_redirections,
+        } = self;
+
+        let mut copy_threads = vec![];
+        let out_sink = sink(out_writer, &mut copy_threads)?;
+        let err_sink = sink(err_writer, &mut copy_threads)?;
+        let opened = open_fds(&mut redirections, &mut copy_threads)?;
+        step_redirections.resize_with(steps.len(), FdTable::default);
+        let step_opened = step_redirections
+            .iter_mut()
+            .map(|table| open_fds(table, &mut copy_threads))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut input = match in_reader {
+            //Only the foreground can read from the terminal
+            None if !foreground && !job::job_control() => {
+                StepFd::Fd(File::open("/dev/null")?.into())
+            }
+            None => StepFd::Inherit,
+            Some(reader) => StepFd::Fd(source(reader, &mut copy_threads)?),
+        };
 
-        let mut last_out = curr_step.run(&pipeline_input)?;
+        let step_count = steps.len();
+        let mut running = Vec::with_capacity(step_count);
+        let mut pgid = None;
+        let mut failure = None;
+
+        for (i, step) in steps.into_iter().enumerate() {
+            let is_last = i + 1 == step_count;
+            let pipe = pipes.get(i).copied();
+
+            //Pipe to the next Step
+            let (next_input, output) = if is_last {
+                (StepFd::Inherit, StepFd::Fd(out_sink.try_clone()?))
+            } else {
+                let (r, w) = io::pipe()?;
+                (StepFd::Fd(r.into()), StepFd::Fd(w.into()))
+            };
+            let errors = match pipe {
+                Some(Pipe::Err) if !is_last => output.try_clone()?,
+                _ => StepFd::Fd(err_sink.try_clone()?),
+            };
 
-        //For each pipe, we redirect output / err according to pipe type
-        for pipe in self.pipes.into_iter() {
-            let curr_input = match pipe {
-                // we want to collect ONLY stdout
-                Pipe::Std => last_out.stdout,
-                // We want to collect both stderr and stdout
-                Pipe::Err => {
-                    last_out.stdout.append(&mut last_out.stderr);
-                    last_out.stdout
+            //The input of the whole Pipeline goes to the first Step, its output comes from the last one
+            let applies = |fd| match fd {
+                0 => i == 0,
+                1 => is_last,
+                _ => true,
+            };
+            let fds = (0..).zip([input, output, errors]).collect();
+            let fds = redirect_fds(fds, &redirections, &opened, applies).and_then(|fds| {
+                redirect_fds(fds, &step_redirections[i], &step_opened[i], |_| true)
+            });
+            let mut fds = match fds {
+                Ok(fds) => fds,
+                Err(e) => {
+                    failure.get_or_insert(e);
+                    input = next_input;
+                    continue;
                 }
             };
 
-            dbg!(String::from_utf8_lossy(&curr_input));
-
-            curr_step = step_iter.next().unwrap();
+            match step {
+                Step::Command(command) => {
+                    let stdin = fds.remove(&0).map_or_else(Stdio::inherit, StepFd::stdio);
+                    let stdout = fds.remove(&1).map_or_else(Stdio::inherit, StepFd::stdio);
+                    let stderr = fds.remove(&2).map_or_else(Stdio::inherit, StepFd::stdio);
+                    //Other fds, and closed standard ones, are set up by the process itself
+                    let others = fds
+                        .into_iter()
+                        .filter_map(|(fd, step_fd)| match step_fd {
+                            StepFd::Fd(src) if fd > 2 => Some((fd, Some(src))),
+                            StepFd::Closed => Some((fd, None)),
+                            _ => None,
+                        })
+                        .collect();
+                    match step::spawn(command, [stdin, stdout, stderr], others, pgid, foreground) {
+                        Ok(child) => {
+                            let pid = Pid::from_raw(child.id() as i32);
+                            if job::job_control() {
+                                pgid.get_or_insert(pid);
+                            }
+                            running.push(Running::Process(pid, None));
+                        }
+                        Err(e) => {
+                            failure.get_or_insert(e);
+                        }
+                    }
+                }
+                Step::Builtin(builtin) => {
+                    //None of the builtins read their input, so it isn't waited for: `yes | echo x` finishes
+                    fds.remove(&0);
+                    let stdout = fds.remove(&1).unwrap_or(StepFd::Closed);
+                    let stderr = fds.remove(&2).unwrap_or(StepFd::Closed);
+                    let run = move || -> Result<StepOutput> {
+                        let out = builtin.run(&[])?;
+                        stderr.write_all(&out.stderr)?;
+                        stdout.write_all(&out.stdout)?;
+                        Ok(out)
+                    };
+                    if is_last {
+                        //The last Step runs in the shell itself, so `cd` and `export` change it. A background
+                        //job is given a copy of the shell beforehand, see `ast::Pipeline::spawn`
+                        match run() {
+                            Ok(out) => running.push(Running::Done(out)),
+                            Err(e) => {
+                                failure.get_or_insert(e);
+                            }
+                        }
+                        input = next_input;
+                        continue;
+                    }
 
-            dbg!(&curr_step);
+                    //Otherwise in a copy of the shell, like a Command. Without the reading end of its own output,
+                    //so that it can tell when the next Step is gone
+                    let own_output = match &next_input {
+                        StepFd::Fd(fd) => Some(fd.as_raw_fd()),
+                        _ => None,
+                    };
+                    let child = move || {
+                        if let Some(fd) = own_output {
+                            let _ = close(fd);
+                        }
+                        match run() {
+                            Ok(out) => out.status(),
+                            Err(e) => {
+                                eprintln!("{}", e);
+                                1
+                            }
+                        }
+                    };
+                    match step::fork(child, pgid, foreground) {
+                        Ok(pid) => {
+                            if job::job_control() {
+                                pgid.get_or_insert(pid);
+                            }
+                            running.push(Running::Process(pid, None));
+                        }
+                        Err(e) => {
+                            failure.get_or_insert(e);
+                        }
+                    }
+                }
+            }
 
-            last_out = curr_step.run(&curr_input)?;
+            input = next_input;
         }
 
-        dbg!(&last_out.code);
-        dbg!(&last_out.success);
-
-        //Appends Output INTO Err, write both to the same destination
-        //If we simply wrote each to it's destination, in case of `&>`
-        //Output would overrwrite Err.
-        if self.redirection_write_type == Some(Redirection::WriteOutErr)
-            || self.redirection_write_type == Some(Redirection::AppendOutErr)
-        {
-            last_out.stderr.append(&mut last_out.stdout);
-            self.out_writer.write_all(&last_out.stderr)?;
-        } else {
-            self.err_writer.write_all(&last_out.stderr)?;
-            self.out_writer.write_all(&last_out.stdout)?;
-        }
+        Ok(RunningPipeline {
+            pgid,
+            steps: running,
+            copy_threads,
+            failure,
+        })
+    }
+}
 
-        Ok(last_out)
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, step) in self.steps.iter().enumerate() {
+            if i > 0 {
+                match self.pipes.get(i - 1) {
+                    Some(Pipe::Err) => write!(f, " |& ")?,
+                    _ => write!(f, " | ")?,
+                }
+            }
+            write!(f, "{}", step)?;
+        }
+        Ok(())
     }
 }
 
 //********************/
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test {
     use super::*;
+    use crate::{error::ShellError, parser};
     use std::fs::{self, File};
 
+    /// Parses and expands a line made of a single Pipeline, as typed by the user
+    fn parse(line: &str) -> std::result::Result<Pipeline, ShellError> {
+        match parser::parse(line)?.items.pop() {
+            Some(item) => item.sublist.first.expand(),
+            None => Ok(Pipeline::from_steps(vec![], vec![], vec![])?),
+        }
+    }
+
     #[test]
     fn simple_pipeline() {
         let p = Pipeline {
@@ -203,7 +655,8 @@ mod test {
             in_reader: None,
             out_writer: Box::new(std::io::stdout()),
             err_writer: Box::new(std::io::stderr()),
-            redirection_write_type: None,
+            redirections: FdTable::default(),
+            step_redirections: vec![],
         };
         let r = p.run().unwrap();
         assert_eq!(r.success, true);
@@ -224,7 +677,8 @@ mod test {
             in_reader: Some(Box::new(File::open("tests/lorem").unwrap())),
             out_writer: Box::new(std::io::stdout()),
             err_writer: Box::new(std::io::stderr()),
-            redirection_write_type: None,
+            redirections: FdTable::default(),
+            step_redirections: vec![],
         };
         let r = p.run().unwrap();
         assert_eq!(r.success, true);
@@ -238,7 +692,8 @@ mod test {
             in_reader: None,
             out_writer: Box::new(std::io::stdout()),
             err_writer: Box::new(std::io::stderr()),
-            redirection_write_type: None,
+            redirections: FdTable::default(),
+            step_redirections: vec![],
         };
 
         let r = p.run();
@@ -278,7 +733,8 @@ mod test {
             in_reader: None,
             out_writer: Box::new(std::io::stdout()),
             err_writer: Box::new(std::io::stderr()),
-            redirection_write_type: None,
+            redirections: FdTable::default(),
+            step_redirections: vec![],
         };
 
         let r = p.run().unwrap();
@@ -287,12 +743,7 @@ mod test {
 
     #[test]
     fn parse_simple_pipeline() {
-        let input = "echo \"asd\" |& grep a | wc -c"
-            .to_owned()
-            .split_whitespace()
-            .map(|s| s.to_owned())
-            .collect();
-        let p_str = Pipeline::new(input).unwrap();
+        let p_str = parse("echo \"asd\" |& grep a | wc -c").unwrap();
         dbg!(&p_str);
 
         let p = Pipeline {
@@ -305,20 +756,15 @@ mod test {
             in_reader: None,
             out_writer: Box::new(std::io::stdout()),
             err_writer: Box::new(std::io::stderr()),
-            redirection_write_type: None,
+            redirections: FdTable::default(),
+            step_redirections: vec![],
         };
         assert_eq!(p.pipes, p_str.pipes);
     }
 
     #[test]
     fn parse_pipeline_new_output() {
-        let input = "echo -n abcde | tr -d a | wc -c > tests/output_new"
-            .to_owned()
-            .split_whitespace()
-            .map(|s| s.to_owned())
-            .collect();
-
-        let p_str = Pipeline::new(input).unwrap();
+        let p_str = parse("echo -n abcde | tr -d a | wc -c > tests/output_new").unwrap();
 
         let p = Pipeline {
             steps: vec![
@@ -351,7 +797,8 @@ mod test {
             in_reader: None,
             out_writer: Box::new(File::create("tests/output_new").unwrap()),
             err_writer: Box::new(std::io::stderr()),
-            redirection_write_type: None,
+            redirections: FdTable::default(),
+            step_redirections: vec![],
         };
 
         assert_eq!(p.pipes, p_str.pipes);
@@ -359,44 +806,32 @@ mod test {
         let bla = format!("{:?}", p.out_writer);
         let bla: Vec<&str> = bla.split(",").collect();
 
-        let lab = format!("{:?}", p_str.out_writer);
-        let lab: Vec<&str> = lab.split(",").collect();
+        //The output is redirected on top of the default writer
+        let lab = format!("{:?}", p_str.step_redirections[2].opened(1).unwrap());
+        let lab: Vec<&str> = lab.trim_end_matches(')').split(",").collect();
 
         assert_eq!(bla[1..], lab[1..]);
     }
 
     #[test]
     fn parse_empty_pipeline() {
-        let p = Pipeline::new(vec![]);
+        let p = parse("");
         assert_eq!(p.is_ok(), true);
         dbg!(&p);
     }
 
     #[test]
     fn parse_non_existing_input() {
-        let c = Pipeline::new(
-            "wc -c < tests/inputs > tests/output"
-                .to_owned()
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect(),
-        );
-        assert!(c.is_err());
-        assert_eq!(c.unwrap_err().kind(), ErrorKind::NotFound);
+        let c = parse("wc -c < tests/inputs > tests/output");
+        assert!(matches!(c, Err(ShellError::Io(e)) if e.kind() == ErrorKind::NotFound));
     }
 
     #[test]
     fn simple_pipeline_read_existing_write_output_existing_file() {
-        Pipeline::new(
-            "wc -c < tests/lorem > tests/output"
-                .to_owned()
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect(),
-        )
-        .unwrap()
-        .run()
-        .unwrap();
+        parse("wc -c < tests/lorem > tests/output")
+            .unwrap()
+            .run()
+            .unwrap();
 
         let mut buff = String::new();
         let mut file = File::open("tests/output").unwrap();
@@ -406,26 +841,14 @@ mod test {
 
     #[test]
     fn simple_pipeline_overwrite_output_file() {
-        Pipeline::new(
-            "wc -c < tests/lorem > tests/output"
-                .to_owned()
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect(),
-        )
-        .unwrap()
-        .run()
-        .unwrap();
-        Pipeline::new(
-            "wc -w < tests/lorem > tests/output"
-                .to_owned()
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect(),
-        )
-        .unwrap()
-        .run()
-        .unwrap();
+        parse("wc -c < tests/lorem > tests/output")
+            .unwrap()
+            .run()
+            .unwrap();
+        parse("wc -w < tests/lorem > tests/output")
+            .unwrap()
+            .run()
+            .unwrap();
 
         let mut buff = String::new();
         let mut file = File::open("tests/output").unwrap();
@@ -435,45 +858,133 @@ mod test {
 
     #[test]
     fn simple_pipeline_empty_out_redir() {
-        let p = Pipeline::new(
-            "wc -c < "
-                .to_owned()
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect(),
-        );
-        assert_eq!(p.is_err(), true);
-        assert_eq!(p.unwrap_err().kind(), ErrorKind::InvalidInput);
+        let p = parse("wc -c < ");
+        assert!(matches!(p, Err(ShellError::Parse(_))));
     }
 
     #[test]
     fn three_step_pipeline() {
-        let p_res = Pipeline::new(
-            "echo -n abcde | tr -d a | wc -c"
-                .to_owned()
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect(),
-        )
-        .unwrap()
-        .run()
-        .unwrap();
+        let output = OutputBuffer::new();
+        let p_res = parse("echo -n abcde | tr -d a | wc -c")
+            .unwrap()
+            .out_writer(Box::new(output.clone()))
+            .run()
+            .unwrap();
         dbg!(&p_res);
-        assert_eq!(String::from_utf8(p_res.stdout).unwrap().trim(), "4")
+        assert_eq!(String::from_utf8(output.take()).unwrap().trim(), "4")
+    }
+
+    #[test]
+    fn pipeline_infinite_producer() {
+        let output = OutputBuffer::new();
+        let p_res = parse("yes | head -n 2")
+            .unwrap()
+            .out_writer(Box::new(output.clone()))
+            .run()
+            .unwrap();
+        assert!(p_res.success);
+        assert_eq!(output.take(), b"y\ny\n");
+    }
+
+    #[test]
+    fn pipeline_err_pipe_merges_output() {
+        let output = OutputBuffer::new();
+        parse("ls tests/err erro |& sort")
+            .unwrap()
+            .out_writer(Box::new(output.clone()))
+            .run()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output.take()).unwrap(),
+            "ls: cannot access 'erro': No such file or directory\ntests/err\n"
+        );
+    }
+
+    #[test]
+    fn pipeline_duplicate_err_into_pipe() {
+        let output = OutputBuffer::new();
+        parse("ls tests/err erro 2>&1 | sort")
+            .unwrap()
+            .out_writer(Box::new(output.clone()))
+            .run()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output.take()).unwrap(),
+            "ls: cannot access 'erro': No such file or directory\ntests/err\n"
+        );
+    }
+
+    #[test]
+    fn pipeline_step_redirections() {
+        let output = OutputBuffer::new();
+        parse("ls tests/err erro 2>&1 > tests/step_output | sed s/^/piped:/")
+            .unwrap()
+            .out_writer(Box::new(output.clone()))
+            .run()
+            .unwrap();
+        let file = std::fs::read_to_string("tests/step_output").unwrap();
+        std::fs::remove_file("tests/step_output").unwrap();
+
+        //The errors go where the output was before it was redirected, the pipe
+        assert_eq!(
+            String::from_utf8(output.take()).unwrap(),
+            "piped:ls: cannot access 'erro': No such file or directory\n"
+        );
+        assert_eq!(file, "tests/err\n");
+    }
+
+    #[test]
+    fn pipeline_builtin_step() {
+        let output = OutputBuffer::new();
+        let p_res = parse("cd . | wc -c")
+            .unwrap()
+            .out_writer(Box::new(output.clone()))
+            .run()
+            .unwrap();
+        assert!(p_res.success);
+        assert_eq!(String::from_utf8(output.take()).unwrap().trim(), "0");
+    }
+
+    #[test]
+    fn pipeline_builtin_steps_outside_the_shell() {
+        let previous_dir = std::env::current_dir().unwrap();
+        let output = OutputBuffer::new();
+        let p_res = parse("cd / | pwd")
+            .unwrap()
+            .out_writer(Box::new(output.clone()))
+            .run()
+            .unwrap();
+        assert!(p_res.success);
+        assert_eq!(std::env::current_dir().unwrap(), previous_dir);
+
+        //The input of a Builtin isn't waited for
+        let p_res = parse("yes | echo x")
+            .unwrap()
+            .out_writer(Box::new(output.clone()))
+            .run()
+            .unwrap();
+        assert!(p_res.success);
+        assert_eq!(
+            String::from_utf8(output.take()).unwrap(),
+            format!("{}\nx\n", previous_dir.display())
+        );
+    }
+
+    #[test]
+    fn pipeline_terminated_by_signal() {
+        let p_res = parse("sh -c 'kill -9 $$'").unwrap().run().unwrap();
+        assert!(!p_res.success);
+        assert_eq!(p_res.code, None);
+        assert_eq!(p_res.signal, Some(9));
+        assert_eq!(p_res.status(), 137);
     }
 
     #[test]
     fn pipeline_write_output_create_new_file() {
-        let _p = Pipeline::new(
-            "echo -n abcde | tr -d a | wc -c > tests/output_new"
-                .to_owned()
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect(),
-        )
-        .unwrap()
-        .run()
-        .unwrap();
+        let _p = parse("echo -n abcde | tr -d a | wc -c > tests/output_new")
+            .unwrap()
+            .run()
+            .unwrap();
 
         let mut buff = String::new();
         let mut file = File::open("tests/output_new").unwrap();
@@ -484,27 +995,12 @@ mod test {
 
     #[test]
     fn pipeline_append_output_existing_file() {
-        Pipeline::new(
-            "echo test > tests/output"
-                .to_owned()
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect(),
-        )
-        .unwrap()
-        .run()
-        .unwrap();
-
-        let _p = Pipeline::new(
-            "echo -n abcde | tr -d a | wc -c >> tests/output"
-                .to_owned()
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect(),
-        )
-        .unwrap()
-        .run()
-        .unwrap();
+        parse("echo test > tests/output").unwrap().run().unwrap();
+
+        let _p = parse("echo -n abcde | tr -d a | wc -c >> tests/output")
+            .unwrap()
+            .run()
+            .unwrap();
 
         let mut buff = String::new();
         let mut file = File::open("tests/output").unwrap();
@@ -514,27 +1010,12 @@ mod test {
 
     #[test]
     fn pipeline_append_err_existing_file() {
-        Pipeline::new(
-            "echo test > tests/output"
-                .to_owned()
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect(),
-        )
-        .unwrap()
-        .run()
-        .unwrap();
-
-        let _p = Pipeline::new(
-            "echo -n abcde | tr -d a | wc -x 2>> tests/output"
-                .to_owned()
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect(),
-        )
-        .unwrap()
-        .run()
-        .unwrap();
+        parse("echo test > tests/output").unwrap().run().unwrap();
+
+        let _p = parse("echo -n abcde | tr -d a | wc -x 2>> tests/output")
+            .unwrap()
+            .run()
+            .unwrap();
 
         let mut buff = String::new();
         let mut file = File::open("tests/output").unwrap();
@@ -546,37 +1027,25 @@ mod test {
     }
     #[test]
     fn pipeline_write_error_existing_file() {
-        let res = Pipeline::new(
-            "ping a 2> tests/err"
-                .to_owned()
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect(),
-        )
-        .unwrap()
-        .run()
-        .unwrap();
+        let res = parse("ls a 2> tests/err").unwrap().run().unwrap();
         assert_eq!(res.success, false);
         assert_ne!(res.code, Some(0));
 
         let mut buff = String::new();
         let mut file = File::open("tests/err").unwrap();
         file.read_to_string(&mut buff).unwrap();
-        assert_eq!("ping: a: Name or service not known", buff.trim());
+        assert_eq!(
+            "ls: cannot access 'a': No such file or directory",
+            buff.trim()
+        );
     }
 
     #[test]
     fn pipeline_write_output_and_error_existing_file() {
-        let res = Pipeline::new(
-            "ls tests/err erro &> tests/output"
-                .to_owned()
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect(),
-        )
-        .unwrap()
-        .run()
-        .unwrap();
+        let res = parse("ls tests/err erro &> tests/output")
+            .unwrap()
+            .run()
+            .unwrap();
         assert_eq!(res.success, false);
         assert_ne!(res.code, Some(0));
 
@@ -591,16 +1060,10 @@ mod test {
 
     #[test]
     fn pipeline_write_output_and_error_existing_file_alt() {
-        let res = Pipeline::new(
-            "ls tests/err erro 2>&1 tests/output"
-                .to_owned()
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect(),
-        )
-        .unwrap()
-        .run()
-        .unwrap();
+        let res = parse("ls tests/err erro > tests/output 2>&1")
+            .unwrap()
+            .run()
+            .unwrap();
         assert_eq!(res.success, false);
         assert_ne!(res.code, Some(0));
 
@@ -615,27 +1078,12 @@ mod test {
 
     #[test]
     fn pipeline_append_output_and_error_existing_file() {
-        Pipeline::new(
-            "echo test > tests/output"
-                .to_owned()
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect(),
-        )
-        .unwrap()
-        .run()
-        .unwrap();
-
-        let res = Pipeline::new(
-            "ls tests/err erro &>> tests/output"
-                .to_owned()
-                .split_whitespace()
-                .map(|s| s.to_owned())
-                .collect(),
-        )
-        .unwrap()
-        .run()
-        .unwrap();
+        parse("echo test > tests/output").unwrap().run().unwrap();
+
+        let res = parse("ls tests/err erro &>> tests/output")
+            .unwrap()
+            .run()
+            .unwrap();
         assert_eq!(res.success, false);
         assert_ne!(res.code, Some(0));
 
@@ -0,0 +1,170 @@
+//! Rendering of the prompt templates, `PS1` / `PS2` or the `prompt` / `secondary_prompt` settings.
+//!
+//! Templates use the bash escapes, rendered again every time the prompt is shown:
+//!
+//! * `\u` user name, `\h` host name up to the first `.`, `\H` full host name
+//! * `\w` current directory (`~` for the home directory), `\W` its last component
+//! * `\$` `#` for root, `$` otherwise, `\?` exit status of the last command, `\j` number of jobs
+//! * `\t` time as HH:MM:SS, `\T` the same in 12-hour format, `\A` as HH:MM, `\d` date as "Tue May 26"
+//! * `\e` escape, used for ANSI colors (e.g. `\e[32m`), `\NNN` octal character, `\a` bell, `\n` new line
+//! * `\[` and `\]` are accepted, but not needed, as the line editor already skips color sequences
+//! * `\s` shell name, `\v` version, `\\` backslash
+use crate::{job, state};
+use nix::{
+    libc,
+    unistd::{gethostname, getuid, User},
+};
+use std::{env, path::Path};
+
+const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Expands the escapes of a prompt template
+pub fn render(template: &str) -> String {
+    let mut prompt = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            prompt.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('u') => prompt.push_str(&user_name()),
+            Some('h') => prompt.push_str(host_name().split('.').next().unwrap_or_default()),
+            Some('H') => prompt.push_str(&host_name()),
+            Some('w') => {
+                let cwd = env::current_dir().unwrap_or_default();
+                prompt.push_str(&tilde_path(&cwd, dirs::home_dir().as_deref()));
+            }
+            Some('W') => {
+                let cwd = env::current_dir().unwrap_or_default();
+                match cwd.file_name() {
+                    _ if Some(cwd.as_path()) == dirs::home_dir().as_deref() => prompt.push('~'),
+                    Some(name) => prompt.push_str(&name.to_string_lossy()),
+                    None => prompt.push_str(&cwd.to_string_lossy()),
+                }
+            }
+            Some('$') => prompt.push(if getuid().is_root() { '#' } else { '$' }),
+            Some('?') => prompt.push_str(&state::last_status().to_string()),
+            Some('j') => prompt.push_str(&job::count().to_string()),
+            Some(c @ 't') | Some(c @ 'T') | Some(c @ 'A') | Some(c @ 'd') => {
+                prompt.push_str(&time(c))
+            }
+            Some('s') => prompt.push_str("shrimp"),
+            Some('v') => prompt.push_str(env!("CARGO_PKG_VERSION")),
+            Some('e') => prompt.push('\x1b'),
+            Some('a') => prompt.push('\x07'),
+            Some('n') => prompt.push('\n'),
+            Some('r') => prompt.push('\r'),
+            Some('\\') => prompt.push('\\'),
+            Some('[') | Some(']') => {}
+            Some(d @ '0'..='7') => {
+                let mut code = d.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            code = code * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                prompt.extend(char::from_u32(code));
+            }
+            //Unknown escapes are kept as they are
+            Some(c) => {
+                prompt.push('\\');
+                prompt.push(c);
+            }
+            None => prompt.push('\\'),
+        }
+    }
+    prompt
+}
+
+fn user_name() -> String {
+    match User::from_uid(getuid()) {
+        Ok(Some(user)) => user.name,
+        _ => state::variable("USER").unwrap_or_default(),
+    }
+}
+
+fn host_name() -> String {
+    let mut buf = [0u8; 256];
+    gethostname(&mut buf)
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// `path`, with the `home` directory replaced by `~`
+fn tilde_path(path: &Path, home: Option<&Path>) -> String {
+    match home.and_then(|home| path.strip_prefix(home).ok()) {
+        Some(rest) if rest.as_os_str().is_empty() => String::from("~"),
+        Some(rest) => format!("~/{}", rest.to_string_lossy()),
+        None => path.to_string_lossy().into_owned(),
+    }
+}
+
+/// Current local time, in the format of the `\t`, `\T`, `\A` or `\d` escapes
+fn time(format: char) -> String {
+    // Safety: `localtime_r` only writes into `tm`, which is fully initialized by it on success
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            return String::new();
+        }
+        tm
+    };
+
+    match format {
+        't' => format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec),
+        'T' => format!(
+            "{:02}:{:02}:{:02}",
+            (tm.tm_hour + 11) % 12 + 1,
+            tm.tm_min,
+            tm.tm_sec
+        ),
+        'A' => format!("{:02}:{:02}", tm.tm_hour, tm.tm_min),
+        _ => format!(
+            "{} {} {:02}",
+            DAYS[tm.tm_wday as usize % 7],
+            MONTHS[tm.tm_mon as usize % 12],
+            tm.tm_mday
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render_escapes() {
+        assert_eq!(render(">> "), ">> ");
+        assert_eq!(render("\\\\ \\n\\101\\e[0m\\[\\]"), "\\ \nA\x1b[0m");
+        assert_eq!(render("\\x \\"), "\\x \\");
+        assert_eq!(render("\\?"), state::last_status().to_string());
+        assert_eq!(
+            render("\\s-\\v"),
+            format!("shrimp-{}", env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(render("\\A").len(), 5);
+    }
+
+    #[test]
+    fn tilde_home() {
+        let home = Path::new("/home/user");
+        assert_eq!(tilde_path(Path::new("/home/user"), Some(home)), "~");
+        assert_eq!(tilde_path(Path::new("/home/user/src"), Some(home)), "~/src");
+        assert_eq!(
+            tilde_path(Path::new("/home/username"), Some(home)),
+            "/home/username"
+        );
+        assert_eq!(tilde_path(Path::new("/tmp"), None), "/tmp");
+    }
+}
@@ -1,22 +1,44 @@
 ///Module with functions to Handle IO Redirections
 use std::{
+    collections::BTreeMap,
+    fmt,
     fs::{File, OpenOptions},
-    io::Result,
-    net::{SocketAddr, ToSocketAddrs},
+    io::{Cursor, Error, ErrorKind, Result},
+    os::unix::io::{OwnedFd, RawFd},
     str::FromStr,
 };
 
-use crate::pipeline::{PipelineReader, PipelineWriter};
+use crate::{
+    network::Address,
+    pipeline::{PipelineReader, PipelineWriter},
+    state::{self, ShellOption},
+};
 
-#[derive(Debug, std::cmp::PartialEq)]
+/// A redirection operator. Those that take a file descriptor number (e.g. `2>`) hold it, or the default one
+#[derive(Debug, Clone, Copy, std::cmp::PartialEq)]
 pub enum Redirection {
-    ReadIn,
-    WriteOut,
-    AppendOut,
-    WriteErr,
-    AppendErr,
+    /// `[n]<`, fd 0 by default
+    ReadIn(RawFd),
+    /// `[n]>`, fd 1 by default
+    WriteOut(RawFd),
+    /// `[n]>>`, fd 1 by default
+    AppendOut(RawFd),
+    /// `[n]>|`, fd 1 by default, like `WriteOut` but overwrites existing files even with the `noclobber` option
+    Clobber(RawFd),
+    /// `[n]<>`, the file is opened for both reading and writing, fd 0 by default
+    ReadWrite(RawFd),
     WriteOutErr,
     AppendOutErr,
+    /// `[n]<&m`, fd 0 by default, see `DuplicateOut`
+    DuplicateIn(RawFd),
+    /// `[n]>&m`, fd 1 by default: n becomes a copy of m. With `-` as target, n is closed instead
+    DuplicateOut(RawFd),
+    /// `<<`, the here-document's body is read from the lines following the command
+    HereDoc,
+    /// `<<-`, like `<<` with the leading tabs of the body's lines and of the delimiter line removed
+    HereDocStripTabs,
+    /// `<<<`, a single word followed by a new line
+    HereString,
 }
 
 #[derive(Debug, std::cmp::PartialEq)]
@@ -26,108 +48,343 @@ impl FromStr for Redirection {
     type Err = RedirectionParseError;
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         match s {
-            "<" => Ok(Redirection::ReadIn),
-            ">" | "1>" => Ok(Redirection::WriteOut),
-            ">>" => Ok(Redirection::AppendOut),
-            "2>" => Ok(Redirection::WriteErr),
-            "2>>" => Ok(Redirection::AppendErr),
-            "&>" | "2>&1" => Ok(Redirection::WriteOutErr),
-            "&>>" => Ok(Redirection::AppendOutErr),
+            "&>" => return Ok(Redirection::WriteOutErr),
+            "&>>" => return Ok(Redirection::AppendOutErr),
+            "<<" => return Ok(Redirection::HereDoc),
+            "<<-" => return Ok(Redirection::HereDocStripTabs),
+            "<<<" => return Ok(Redirection::HereString),
+            _ => {}
+        }
+
+        let operator = s.trim_start_matches(|c: char| c.is_ascii_digit());
+        let fd = match &s[..s.len() - operator.len()] {
+            "" => None,
+            digits => Some(digits.parse().map_err(|_| RedirectionParseError {})?),
+        };
+        match operator {
+            "<" => Ok(Redirection::ReadIn(fd.unwrap_or(0))),
+            ">" => Ok(Redirection::WriteOut(fd.unwrap_or(1))),
+            ">>" => Ok(Redirection::AppendOut(fd.unwrap_or(1))),
+            ">|" => Ok(Redirection::Clobber(fd.unwrap_or(1))),
+            "<>" => Ok(Redirection::ReadWrite(fd.unwrap_or(0))),
+            "<&" => Ok(Redirection::DuplicateIn(fd.unwrap_or(0))),
+            ">&" => Ok(Redirection::DuplicateOut(fd.unwrap_or(1))),
             _ => Err(RedirectionParseError {}),
         }
     }
 }
 
+impl fmt::Display for Redirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        //The file descriptor is only shown when it isn't the default one
+        let (fd, default, operator) = match self {
+            Redirection::ReadIn(fd) => (*fd, 0, "<"),
+            Redirection::WriteOut(fd) => (*fd, 1, ">"),
+            Redirection::AppendOut(fd) => (*fd, 1, ">>"),
+            Redirection::Clobber(fd) => (*fd, 1, ">|"),
+            Redirection::ReadWrite(fd) => (*fd, 0, "<>"),
+            Redirection::DuplicateIn(fd) => (*fd, 0, "<&"),
+            Redirection::DuplicateOut(fd) => (*fd, 1, ">&"),
+            Redirection::WriteOutErr => return write!(f, "&>"),
+            Redirection::AppendOutErr => return write!(f, "&>>"),
+            Redirection::HereDoc => return write!(f, "<<"),
+            Redirection::HereDocStripTabs => return write!(f, "<<-"),
+            Redirection::HereString => return write!(f, "<<<"),
+        };
+        if fd != default {
+            write!(f, "{}", fd)?;
+        }
+        write!(f, "{}", operator)
+    }
+}
+
+/// Where a file descriptor points to, once the redirections are done
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FdTarget {
+    /// The file descriptor as it was before any redirection: the Step's end of a pipe, the Pipeline's reader or
+    /// writers, or the shell's own one
+    Inherit(RawFd),
+    /// One of the files (or here-documents) opened by the redirections, by its index in `FdTable::opened`
+    Opened(usize),
+    Closed,
+}
+
+/// A file, or other source or destination, opened by a redirection
+#[derive(Debug)]
+pub enum Opened {
+    Reader(Box<dyn PipelineReader>),
+    Writer(Box<dyn PipelineWriter>),
+    /// `<>`, a file or a socket
+    ReadWrite(OwnedFd),
+}
+
+/// File descriptors changed by redirections, evaluated from left to right: `2>&1 >out` sends the errors where the
+/// output was before, `>out 2>&1` sends both into `out`.
+///
+/// Descriptors that aren't in the table are left as they are
+#[derive(Debug, Default)]
+pub struct FdTable {
+    opened: Vec<Opened>,
+    fds: BTreeMap<RawFd, FdTarget>,
+}
+
+impl FdTable {
+    /// Evaluates the `redirections`, with their source or destination, opening the files they name
+    pub fn new(redirections: Vec<(Redirection, String)>) -> Result<FdTable> {
+        let mut table = FdTable::default();
+        for (redirection, src_or_dst) in redirections {
+            redirection.configure_redirection(&src_or_dst, &mut table)?;
+        }
+        Ok(table)
+    }
+
+    /// Where `fd` points to
+    pub fn target(&self, fd: RawFd) -> FdTarget {
+        self.fds.get(&fd).copied().unwrap_or(FdTarget::Inherit(fd))
+    }
+
+    /// Redirected file descriptors, and where they point to
+    pub fn fds(&self) -> impl Iterator<Item = (RawFd, FdTarget)> + '_ {
+        self.fds.iter().map(|(fd, target)| (*fd, *target))
+    }
+
+    /// Takes the opened files, in the order of their index in `FdTarget::Opened`
+    pub fn take_opened(&mut self) -> Vec<Opened> {
+        std::mem::take(&mut self.opened)
+    }
+
+    /// What `fd` points to, if it's an opened file
+    pub fn opened(&self, fd: RawFd) -> Option<&Opened> {
+        match self.target(fd) {
+            FdTarget::Opened(i) => self.opened.get(i),
+            _ => None,
+        }
+    }
+
+    /// Adds a file opened by a redirection, returning the target that points to it
+    fn push(&mut self, opened: Opened) -> FdTarget {
+        self.opened.push(opened);
+        FdTarget::Opened(self.opened.len() - 1)
+    }
+
+    /// What a copy of the file descriptor `src` points to, or `Closed` if it's `-`
+    fn duplicate(&self, src: &str) -> Result<FdTarget> {
+        match src {
+            "-" => Ok(FdTarget::Closed),
+            _ => match src.parse() {
+                Ok(src) => Ok(self.target(src)),
+                Err(_) => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{}: ambiguous redirect", src),
+                )),
+            },
+        }
+    }
+}
+
 impl Redirection {
     pub fn is_redirection(s: &str) -> bool {
         Redirection::from_str(s).is_ok()
     }
 
-    /// Gets the `src_or_dst` and mutable references to the Readers and Writers
-    /// Depending on the type of redirection and the type Reader/Writer of `src_or_dst`
-    /// it updates the mut references accordingly.
-    pub fn configure_redirection(
-        &self,
-        src_or_dst: &str,
-        in_reader: &mut Option<Box<dyn PipelineReader>>,
-        out_writer: &mut Option<Box<dyn PipelineWriter>>,
-        err_writer: &mut Option<Box<dyn PipelineWriter>>,
-    ) -> Result<()> {
-        //src_or_dst is a Socket
-        if let Ok(socket) = src_or_dst.to_socket_addrs() {
-            //TODO 2021-08-28 Implement for network
-            //NOT SURE THIS WORKS FOR URLS
-            match self {
-                Redirection::ReadIn => {}
-                Redirection::WriteOut => {}
-                Redirection::AppendOut => {}
-                Redirection::WriteErr => {}
-                Redirection::AppendErr => {}
-                Redirection::WriteOutErr => {}
-                Redirection::AppendOutErr => {}
+    /// `<<`, `<<-` and `<<<`, whose source is the (expanded) text itself rather than a file
+    pub fn is_here_document(&self) -> bool {
+        matches!(
+            self,
+            Redirection::HereDoc | Redirection::HereDocStripTabs | Redirection::HereString
+        )
+    }
+
+    /// Points the file descriptors of the redirection to `src_or_dst` in the table of those redirected so far,
+    /// opening it if it's a file or a socket
+    pub fn configure_redirection(&self, src_or_dst: &str, fds: &mut FdTable) -> Result<()> {
+        let (redirected, target) = match *self {
+            Redirection::HereDoc | Redirection::HereDocStripTabs | Redirection::HereString => {
+                let body = Cursor::new(src_or_dst.as_bytes().to_vec());
+                (vec![0], fds.push(Opened::Reader(Box::new(body))))
             }
-        }
-        //Default to a path in the filesystem
-        else {
-            //change redir
-            match self {
-                Redirection::ReadIn => {
-                    //Set contents of the reference as
-                    *in_reader = Some(Box::new(File::open(src_or_dst)?));
-                }
-                Redirection::WriteOut => {
-                    *out_writer = Some(Box::new(File::create(src_or_dst)?));
-                }
-                Redirection::AppendOut => {
-                    *out_writer = Some(Box::new(
-                        OpenOptions::new()
-                            .append(true)
-                            .create(true)
-                            .open(src_or_dst)?,
-                    ));
-                }
-                Redirection::WriteErr => {
-                    *err_writer = Some(Box::new(File::create(src_or_dst)?));
-                }
-                Redirection::AppendErr => {
-                    *err_writer = Some(Box::new(
-                        OpenOptions::new()
-                            .append(true)
-                            .create(true)
-                            .open(src_or_dst)?,
-                    ));
-                }
-                //These last two only have out_writer set, as it will be the sole destination
-                //for both output and err
-                Redirection::WriteOutErr => {
-                    *out_writer = Some(Box::new(File::create(src_or_dst)?));
-                }
-                Redirection::AppendOutErr => {
-                    *out_writer = Some(Box::new(
-                        OpenOptions::new()
-                            .append(true)
-                            .create(true)
-                            .open(src_or_dst)?,
-                    ));
-                }
+            //`>&file`, without a number, is another way to write `&>file`
+            Redirection::DuplicateOut(1)
+                if src_or_dst != "-" && src_or_dst.parse::<RawFd>().is_err() =>
+            {
+                return Redirection::WriteOutErr.configure_redirection(src_or_dst, fds);
             }
-            return Ok(());
-        }
+            Redirection::DuplicateIn(fd) | Redirection::DuplicateOut(fd) => {
+                (vec![fd], fds.duplicate(src_or_dst)?)
+            }
+            Redirection::ReadIn(fd) => {
+                let reader = match Address::parse(src_or_dst)? {
+                    Some(address) => address.connect()?.reader(),
+                    None => Box::new(File::open(src_or_dst)?),
+                };
+                (vec![fd], fds.push(Opened::Reader(reader)))
+            }
+            Redirection::WriteOut(fd) => (vec![fd], fds.push(writer(src_or_dst, create)?)),
+            Redirection::Clobber(fd) => (
+                vec![fd],
+                fds.push(writer(src_or_dst, |path| File::create(path))?),
+            ),
+            Redirection::AppendOut(fd) => (vec![fd], fds.push(writer(src_or_dst, append)?)),
+            //Both output and errors go into the same file
+            Redirection::WriteOutErr => (vec![1, 2], fds.push(writer(src_or_dst, create)?)),
+            Redirection::AppendOutErr => (vec![1, 2], fds.push(writer(src_or_dst, append)?)),
+            Redirection::ReadWrite(fd) => {
+                let file: OwnedFd = match Address::parse(src_or_dst)? {
+                    Some(address) => address.connect()?.into(),
+                    None => OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create(true)
+                        .truncate(false)
+                        .open(src_or_dst)?
+                        .into(),
+                };
+                (vec![fd], fds.push(Opened::ReadWrite(file)))
+            }
+        };
 
+        for fd in redirected {
+            fds.fds.insert(fd, target);
+        }
         Ok(())
     }
 }
+
+/// The destination of an output redirection: a socket if `dst` is a network address, where appending is the same
+/// as writing, otherwise the file opened by `open_file`
+fn writer(dst: &str, open_file: fn(&str) -> Result<File>) -> Result<Opened> {
+    let writer: Box<dyn PipelineWriter> = match Address::parse(dst)? {
+        Some(address) => address.connect()?.writer(),
+        None => Box::new(open_file(dst)?),
+    };
+    Ok(Opened::Writer(writer))
+}
+
+/// Creates a file to write to it, or truncates it if it exists. With the `noclobber` option, existing regular files
+/// are an error instead, the check and the creation being a single step. Other existing files, like `/dev/null`,
+/// are opened without truncating them
+fn create(path: &str) -> Result<File> {
+    if !state::option(ShellOption::NoClobber) {
+        return File::create(path);
+    }
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            let file = OpenOptions::new().write(true).open(path)?;
+            if file.metadata()?.is_file() {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{}: cannot overwrite existing file", path),
+                ));
+            }
+            Ok(file)
+        }
+        created => created,
+    }
+}
+
+/// Opens a file to append to it, creating it if needed
+fn append(path: &str) -> Result<File> {
+    OpenOptions::new().append(true).create(true).open(path)
+}
+
+#[cfg(test)]
 mod test {
     use super::*;
     #[test]
     fn parse_redirection() {
-        assert_eq!(Redirection::from_str("<"), Ok(Redirection::ReadIn));
-        assert_eq!(Redirection::from_str(">"), Ok(Redirection::WriteOut));
-        assert_eq!(Redirection::from_str(">>"), Ok(Redirection::AppendOut));
-        assert_eq!(Redirection::from_str("2>"), Ok(Redirection::WriteErr));
-        assert_eq!(Redirection::from_str("2>>"), Ok(Redirection::AppendErr));
+        assert_eq!(Redirection::from_str("<"), Ok(Redirection::ReadIn(0)));
+        assert_eq!(Redirection::from_str(">"), Ok(Redirection::WriteOut(1)));
+        assert_eq!(Redirection::from_str(">>"), Ok(Redirection::AppendOut(1)));
+        assert_eq!(Redirection::from_str("2>"), Ok(Redirection::WriteOut(2)));
+        assert_eq!(Redirection::from_str("2>>"), Ok(Redirection::AppendOut(2)));
         assert_eq!(Redirection::from_str("&>"), Ok(Redirection::WriteOutErr));
-        assert_eq!(Redirection::from_str("2>&1"), Ok(Redirection::WriteOutErr));
+        assert_eq!(
+            Redirection::from_str("2>&"),
+            Ok(Redirection::DuplicateOut(2))
+        );
         assert_eq!(Redirection::from_str("&>>"), Ok(Redirection::AppendOutErr));
+        assert_eq!(Redirection::from_str("3<>"), Ok(Redirection::ReadWrite(3)));
+        assert_eq!(Redirection::from_str("<&"), Ok(Redirection::DuplicateIn(0)));
+        assert_eq!(Redirection::from_str("<<"), Ok(Redirection::HereDoc));
+        assert_eq!(
+            Redirection::from_str("<<-"),
+            Ok(Redirection::HereDocStripTabs)
+        );
+        assert_eq!(Redirection::from_str("<<<"), Ok(Redirection::HereString));
+        assert_eq!(Redirection::from_str(">|"), Ok(Redirection::Clobber(1)));
+        assert_eq!(Redirection::from_str("2>|"), Ok(Redirection::Clobber(2)));
+        assert!(Redirection::from_str("2&>").is_err());
+        assert!(Redirection::from_str("99999999999>").is_err());
+
+        assert_eq!(Redirection::WriteOut(1).to_string(), ">");
+        assert_eq!(Redirection::DuplicateOut(2).to_string(), "2>&");
+    }
+
+    #[test]
+    fn evaluate_left_to_right() {
+        let path = "tests/redirection_table";
+        let table = |redirections: &[(&str, &str)]| {
+            FdTable::new(
+                redirections
+                    .iter()
+                    .map(|(r, t)| (Redirection::from_str(r).unwrap(), t.to_string()))
+                    .collect(),
+            )
+        };
+
+        let fds = table(&[(">", path), ("2>&", "1")]).unwrap();
+        assert_eq!(fds.target(1), FdTarget::Opened(0));
+        assert_eq!(fds.target(2), FdTarget::Opened(0));
+
+        let fds = table(&[("2>&", "1"), (">", path)]).unwrap();
+        assert_eq!(fds.target(1), FdTarget::Opened(0));
+        assert_eq!(fds.target(2), FdTarget::Inherit(1));
+
+        let fds = table(&[("3<", path), ("<&", "3"), ("3<&", "-")]).unwrap();
+        assert_eq!(fds.target(0), FdTarget::Opened(0));
+        assert_eq!(fds.target(3), FdTarget::Closed);
+        assert_eq!(fds.target(4), FdTarget::Inherit(4));
+
+        let fds = table(&[(">&", path)]).unwrap();
+        assert_eq!(fds.target(2), FdTarget::Opened(0));
+        assert_eq!(
+            table(&[("2>&", "x")]).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn noclobber_keeps_existing_files() {
+        let path = "tests/redirection_noclobber";
+        let redirect = |operator: &str, target: &str| {
+            FdTable::new(vec![(
+                Redirection::from_str(operator).unwrap(),
+                target.to_owned(),
+            )])
+        };
+        std::fs::write(path, "kept").unwrap();
+
+        state::set_option(ShellOption::NoClobber, true);
+        let overwrite = redirect(">", path).map(|_| ());
+        let overwrite_all = redirect("&>", path).map(|_| ());
+        let kept = std::fs::read_to_string(path).unwrap();
+        let append = redirect(">>", path).map(|_| ());
+        let device = redirect(">", "/dev/null").map(|_| ());
+        let forced = redirect(">|", path).map(|_| ());
+        state::set_option(ShellOption::NoClobber, false);
+        let forced_contents = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            overwrite.unwrap_err().to_string(),
+            "tests/redirection_noclobber: cannot overwrite existing file"
+        );
+        assert_eq!(overwrite_all.unwrap_err().kind(), ErrorKind::AlreadyExists);
+        assert_eq!(kept, "kept");
+        assert!(append.is_ok());
+        assert!(device.is_ok());
+        assert!(forced.is_ok());
+        assert_eq!(forced_contents, "");
     }
 }
@@ -0,0 +1,73 @@
+//! Signal dispositions of the shell and of the processes it starts.
+//!
+//! An interactive shell must survive the signals the terminal sends to the foreground (Ctrl-C, Ctrl-\, Ctrl-Z),
+//! so it ignores them, see `init`. Ignored signals stay ignored across `exec`, so every Command restores the
+//! default dispositions before starting, see `restore_defaults`.
+use nix::sys::signal::{signal, SigHandler, Signal};
+use std::{convert::TryFrom, io::Result};
+
+/// Signals ignored by the interactive shell. `SIGTTIN` and `SIGTTOU` are sent when a background process group
+/// uses the terminal, which the shell does whenever it takes the terminal back from a job
+const SHELL_SIGNALS: [Signal; 5] = [
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTSTP,
+    Signal::SIGTTIN,
+    Signal::SIGTTOU,
+];
+
+/// Ignores the signals in `SHELL_SIGNALS`. Only meant for an interactive shell, a script should still be
+/// interrupted by Ctrl-C
+pub fn init() {
+    for s in SHELL_SIGNALS {
+        // Safety: no handler is installed
+        unsafe {
+            let _ = signal(s, SigHandler::SigIgn);
+        }
+    }
+}
+
+/// Restores the default disposition of the signals ignored by the shell.
+///
+/// Runs in the child process between `fork` and `exec` (see `step::spawn`), so it must stay async-signal-safe
+pub fn restore_defaults() -> Result<()> {
+    for s in SHELL_SIGNALS {
+        // Safety: no handler is installed
+        unsafe {
+            signal(s, SigHandler::SigDfl)?;
+        }
+    }
+    Ok(())
+}
+
+/// Exit code reported for a process terminated by the signal number `signal`, as in `$?`
+pub fn exit_code(signal: i32) -> i32 {
+    128 + signal
+}
+
+/// Message shown when a foreground process is terminated by `signal`, e.g. "terminated by signal 9 (SIGKILL)".
+///
+/// `None` for the signals sent on purpose by the user or by a closing pipe, like the other shells do
+pub fn termination_message(signal: i32) -> Option<String> {
+    match Signal::try_from(signal) {
+        Ok(Signal::SIGINT) | Ok(Signal::SIGPIPE) => None,
+        Ok(s) => Some(format!("terminated by signal {} ({})", signal, s)),
+        Err(_) => Some(format!("terminated by signal {}", signal)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn termination_messages() {
+        assert_eq!(termination_message(Signal::SIGINT as i32), None);
+        assert_eq!(termination_message(Signal::SIGPIPE as i32), None);
+        assert_eq!(
+            termination_message(Signal::SIGKILL as i32).unwrap(),
+            "terminated by signal 9 (SIGKILL)"
+        );
+        assert_eq!(exit_code(Signal::SIGKILL as i32), 137);
+    }
+}
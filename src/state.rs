@@ -0,0 +1,343 @@
+//! State of the shell that outlives a single command line, shared by the whole process like the current directory.
+//!
+//! It's read during expansion, e.g. `$?`, and updated as Pipelines finish
+//!
+//! Options, like `nullglob`, are changed by the `set -o` and `shopt` built-ins.
+//!
+//! Variables are either shell-local or exported. Exported ones make up the whole environment of every Command,
+//! see `exported_variables`. Those inherited by the shell start as exported, its own environment is never changed
+use crate::{job, step::StepOutput};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    io::{Error, Result},
+    process,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        LazyLock, Mutex,
+    },
+};
+
+/// Exit status of the last foreground Pipeline, see `StepOutput::status`
+static LAST_STATUS: AtomicI32 = AtomicI32::new(0);
+
+/// `$0` followed by the arguments of the script, `$1`, `$2`...
+static POSITIONAL_PARAMETERS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Variables of the shell, starting with the exported ones it inherited (those that are valid unicode)
+static VARIABLES: LazyLock<Mutex<BTreeMap<String, Variable>>> = LazyLock::new(|| {
+    let inherited = env::vars_os().filter_map(|(name, value)| {
+        let variable = Variable {
+            value: Some(value.into_string().ok()?),
+            exported: true,
+            readonly: false,
+        };
+        Some((name.into_string().ok()?, variable))
+    });
+    Mutex::new(inherited.collect())
+});
+
+/// Shell options that are on, all of them start off
+static OPTIONS: Mutex<BTreeSet<ShellOption>> = Mutex::new(BTreeSet::new());
+
+/// Options changing how the shell behaves, see `set -o` and `shopt`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ShellOption {
+    /// Wildcards also match the files starting with `.`
+    DotGlob,
+    /// A pattern that matches no path makes the command fail
+    FailGlob,
+    /// `>` and `&>` don't overwrite existing files, `>|` still does
+    NoClobber,
+    /// A pattern that matches no path is removed, instead of being kept as it is
+    NullGlob,
+}
+
+impl ShellOption {
+    pub const ALL: [ShellOption; 4] = [
+        ShellOption::DotGlob,
+        ShellOption::FailGlob,
+        ShellOption::NoClobber,
+        ShellOption::NullGlob,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ShellOption::DotGlob => "dotglob",
+            ShellOption::FailGlob => "failglob",
+            ShellOption::NoClobber => "noclobber",
+            ShellOption::NullGlob => "nullglob",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ShellOption> {
+        ShellOption::ALL
+            .iter()
+            .copied()
+            .find(|option| option.name() == name)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Variable {
+    /// `None` when declared, e.g. by `export NAME`, but never set
+    value: Option<String>,
+    exported: bool,
+    readonly: bool,
+}
+
+/// Records how the last foreground Pipeline finished, for `$?`
+pub fn set_last_status(out: &StepOutput) {
+    LAST_STATUS.store(out.status(), Ordering::SeqCst);
+}
+
+pub fn last_status() -> i32 {
+    LAST_STATUS.load(Ordering::SeqCst)
+}
+
+/// Sets `$0` (the name of the shell or script) and the positional parameters `$1`, `$2`...
+pub fn set_positional_parameters(name: String, args: Vec<String>) {
+    let mut params = POSITIONAL_PARAMETERS.lock().unwrap();
+    *params = args;
+    params.insert(0, name);
+}
+
+/// The positional parameters, without `$0`, as expanded by `$@`
+pub fn positional_parameters() -> Vec<String> {
+    let params = POSITIONAL_PARAMETERS.lock().unwrap();
+    params.iter().skip(1).cloned().collect()
+}
+
+/// The positional parameter `$<n>`, `$0` being the name of the shell or script. `None` if there are less than `n`
+pub fn positional_parameter(n: usize) -> Option<String> {
+    POSITIONAL_PARAMETERS.lock().unwrap().get(n).cloned()
+}
+
+/// Value of the special parameter `$<name>`, if `name` is one: `?` (last exit status), `$` (pid of the shell),
+/// `!` (pid of the last background process, empty if there's none), `0`..`9` (positional parameters),
+/// `#` (their count) and `@` / `*` (all of them, joined by spaces)
+pub fn special_parameter(name: char) -> Option<String> {
+    match name {
+        '0'..='9' => {
+            Some(positional_parameter(name.to_digit(10).unwrap() as usize).unwrap_or_default())
+        }
+        '#' => Some(positional_parameters().len().to_string()),
+        '@' | '*' => Some(positional_parameters().join(" ")),
+        '?' => Some(last_status().to_string()),
+        '$' => Some(process::id().to_string()),
+        '!' => Some(
+            job::last_background_pid()
+                .map(|pid| pid.to_string())
+                .unwrap_or_default(),
+        ),
+        _ => None,
+    }
+}
+
+pub fn option(option: ShellOption) -> bool {
+    OPTIONS.lock().unwrap().contains(&option)
+}
+
+pub fn set_option(option: ShellOption, on: bool) {
+    let mut options = OPTIONS.lock().unwrap();
+    if on {
+        options.insert(option);
+    } else {
+        options.remove(&option);
+    }
+}
+
+/// Checks if `name` can be the name of a variable: letters, digits and `_`, not starting with a digit
+pub fn is_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Value of the variable `name`, shell-local or exported
+pub fn variable(name: &str) -> Option<String> {
+    VARIABLES
+        .lock()
+        .unwrap()
+        .get(name)
+        .and_then(|variable| variable.value.clone())
+}
+
+/// Names of all the variables that are set
+pub fn variable_names() -> BTreeSet<String> {
+    let variables = VARIABLES.lock().unwrap();
+    variables
+        .iter()
+        .filter(|(_, variable)| variable.value.is_some())
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+/// Exported variables that are set and their values, the environment of the Commands
+pub fn exported_variables() -> BTreeMap<String, String> {
+    let variables = VARIABLES.lock().unwrap();
+    variables
+        .iter()
+        .filter(|(_, variable)| variable.exported)
+        .filter_map(|(name, variable)| Some((name.clone(), variable.value.clone()?)))
+        .collect()
+}
+
+/// Read-only variables and their values, if set
+pub fn readonly_variables() -> Vec<(String, Option<String>)> {
+    let variables = VARIABLES.lock().unwrap();
+    variables
+        .iter()
+        .filter(|(_, variable)| variable.readonly)
+        .map(|(name, variable)| (name.clone(), variable.value.clone()))
+        .collect()
+}
+
+/// `NAME=value`, the variable stays exported if it already was, otherwise it's shell-local
+pub fn set_variable(name: &str, value: String) -> Result<()> {
+    with_variable(name, |variable| {
+        if variable.readonly {
+            return Err(readonly_error(name));
+        }
+        variable.value = Some(value);
+        Ok(())
+    })
+}
+
+/// `export NAME[=value]`, the variable is passed to the Commands from now on
+pub fn export_variable(name: &str, value: Option<String>) -> Result<()> {
+    with_variable(name, |variable| {
+        if let Some(value) = value {
+            if variable.readonly {
+                return Err(readonly_error(name));
+            }
+            variable.value = Some(value);
+        }
+        variable.exported = true;
+        Ok(())
+    })
+}
+
+/// `readonly NAME[=value]`, the variable can't be changed or unset from now on
+pub fn readonly_variable(name: &str, value: Option<String>) -> Result<()> {
+    with_variable(name, |variable| {
+        if let Some(value) = value {
+            if variable.readonly {
+                return Err(readonly_error(name));
+            }
+            variable.value = Some(value);
+        }
+        variable.readonly = true;
+        Ok(())
+    })
+}
+
+/// `unset NAME`, removes the variable, exported or not
+pub fn unset_variable(name: &str) -> Result<()> {
+    let mut variables = VARIABLES.lock().unwrap();
+    if matches!(variables.get(name), Some(variable) if variable.readonly) {
+        return Err(readonly_error(name));
+    }
+    variables.remove(name);
+    Ok(())
+}
+
+/// Runs `f` with the entry of the variable `name`, created unset if there's none
+fn with_variable(name: &str, f: impl FnOnce(&mut Variable) -> Result<()>) -> Result<()> {
+    let mut variables = VARIABLES.lock().unwrap();
+    f(variables.entry(name.to_owned()).or_default())
+}
+
+fn readonly_error(name: &str) -> Error {
+    Error::other(format!("{}: readonly variable", name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn last_status_from_output() {
+        let out = StepOutput {
+            success: false,
+            code: None,
+            signal: Some(15),
+            stdout: vec![],
+            stderr: vec![],
+        };
+        set_last_status(&out);
+        assert_eq!(special_parameter('?'), Some("143".to_owned()));
+        assert_eq!(special_parameter('$'), Some(process::id().to_string()));
+        assert_eq!(special_parameter('a'), None);
+    }
+
+    #[test]
+    fn positional_parameters_expansion() {
+        set_positional_parameters(
+            "script.sh".to_owned(),
+            vec!["a".to_owned(), "b c".to_owned()],
+        );
+        assert_eq!(special_parameter('0'), Some("script.sh".to_owned()));
+        assert_eq!(special_parameter('2'), Some("b c".to_owned()));
+        assert_eq!(special_parameter('3'), Some(String::new()));
+        assert_eq!(special_parameter('#'), Some("2".to_owned()));
+        assert_eq!(special_parameter('@'), Some("a b c".to_owned()));
+        assert_eq!(positional_parameters(), vec!["a", "b c"]);
+        set_positional_parameters(String::new(), vec![]);
+    }
+
+    #[test]
+    fn shell_options() {
+        assert_eq!(
+            ShellOption::from_name("nullglob"),
+            Some(ShellOption::NullGlob)
+        );
+        assert_eq!(ShellOption::from_name("nope"), None);
+        assert!(!option(ShellOption::DotGlob));
+        set_option(ShellOption::DotGlob, true);
+        assert!(option(ShellOption::DotGlob));
+        set_option(ShellOption::DotGlob, false);
+        assert!(!option(ShellOption::DotGlob));
+    }
+
+    #[test]
+    fn local_and_exported_variables() {
+        set_variable("SHRIMP_TEST_LOCAL", "a".to_owned()).unwrap();
+        assert_eq!(variable("SHRIMP_TEST_LOCAL"), Some("a".to_owned()));
+        assert!(!exported_variables().contains_key("SHRIMP_TEST_LOCAL"));
+        assert!(variable_names().contains("SHRIMP_TEST_LOCAL"));
+
+        export_variable("SHRIMP_TEST_LOCAL", None).unwrap();
+        assert_eq!(exported_variables()["SHRIMP_TEST_LOCAL"], "a");
+        set_variable("SHRIMP_TEST_LOCAL", "b".to_owned()).unwrap();
+        assert_eq!(exported_variables()["SHRIMP_TEST_LOCAL"], "b");
+
+        unset_variable("SHRIMP_TEST_LOCAL").unwrap();
+        assert_eq!(variable("SHRIMP_TEST_LOCAL"), None);
+        assert!(!exported_variables().contains_key("SHRIMP_TEST_LOCAL"));
+        //The environment of the shell itself is left alone
+        assert!(env::var("SHRIMP_TEST_LOCAL").is_err());
+
+        //Inherited from the environment
+        assert_eq!(variable("PATH"), env::var("PATH").ok());
+        assert_eq!(
+            exported_variables().get("PATH"),
+            env::var("PATH").ok().as_ref()
+        );
+    }
+
+    #[test]
+    fn readonly_variables_cant_change() {
+        readonly_variable("SHRIMP_TEST_READONLY", Some("a".to_owned())).unwrap();
+        assert!(set_variable("SHRIMP_TEST_READONLY", "b".to_owned()).is_err());
+        assert!(unset_variable("SHRIMP_TEST_READONLY").is_err());
+        assert!(export_variable("SHRIMP_TEST_READONLY", None).is_ok());
+        assert_eq!(exported_variables()["SHRIMP_TEST_READONLY"], "a");
+        assert!(readonly_variables()
+            .contains(&("SHRIMP_TEST_READONLY".to_owned(), Some("a".to_owned()))));
+
+        assert!(is_name("_A1"));
+        assert!(!is_name("1A"));
+        assert!(!is_name("A-B"));
+        assert!(!is_name(""));
+    }
+}
@@ -1,9 +1,21 @@
 use std::{
-    io::{prelude::*, Error, ErrorKind, Result},
-    process::{Command, Output, Stdio},
+    fmt,
+    io::{Error, ErrorKind, Result},
+    os::unix::{
+        io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        process::{CommandExt, ExitStatusExt},
+    },
+    process::{Child, Command, ExitStatus, Output, Stdio},
 };
 
-use crate::{builtin::Builtin, redirection};
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, FcntlArg},
+    sys::wait::WaitStatus,
+    unistd::{close, dup2, fork as fork_process, getpgrp, setpgid, tcsetpgrp, ForkResult, Pid},
+};
+
+use crate::{builtin::Builtin, job, signal, state};
 
 /// Step, the basic Unit of execution of a Pipeline. Can either be a Shrimp Built-in function or a Command
 /// Design wise - a "Wrapper" enum was chosen because the Std::Command is a simple struct, it has no trait that builtins could implement (CommandExt are sealed)
@@ -19,27 +31,85 @@ pub enum Step {
 pub struct StepOutput {
     pub success: bool,
     pub code: Option<i32>,
+    /// Signal that terminated the process, in which case there's no `code`
+    pub signal: Option<i32>,
     pub stdout: Vec<u8>,
     pub stderr: Vec<u8>,
 }
 
+impl StepOutput {
+    /// The output of a step that succeeded without writing anything
+    pub fn success() -> StepOutput {
+        StepOutput {
+            success: true,
+            code: Some(0),
+            signal: None,
+            stdout: vec![],
+            stderr: vec![],
+        }
+    }
+
+    /// Exit status as seen by the user in `$?`, 128+N for a process terminated by the signal N
+    pub fn status(&self) -> i32 {
+        match (self.code, self.signal) {
+            (Some(code), _) => code,
+            (None, Some(signal)) => signal::exit_code(signal),
+            (None, None) => i32::from(!self.success),
+        }
+    }
+}
+
+impl From<ExitStatus> for StepOutput {
+    fn from(status: ExitStatus) -> StepOutput {
+        Self {
+            stdout: vec![],
+            stderr: vec![],
+            code: status.code(),
+            signal: status.signal(),
+            success: status.success(),
+        }
+    }
+}
+
 impl From<Output> for StepOutput {
     fn from(output: Output) -> StepOutput {
         Self {
             stdout: output.stdout,
             stderr: output.stderr,
             code: output.status.code(),
+            signal: output.status.signal(),
             success: output.status.success(),
         }
     }
 }
 
+impl From<WaitStatus> for StepOutput {
+    fn from(status: WaitStatus) -> StepOutput {
+        let (code, signal) = match status {
+            WaitStatus::Exited(_, code) => (Some(code), None),
+            WaitStatus::Signaled(_, signal, _) => (None, Some(signal as i32)),
+            _ => (None, None),
+        };
+        Self {
+            stdout: vec![],
+            stderr: vec![],
+            code,
+            signal,
+            success: code == Some(0),
+        }
+    }
+}
+
 impl Step {
     ///Creates a new Step. It will validate if the desired command is a Built-in or an external program and
     /// Return the enum variant accordingly.
     ///
     /// In the (extremely) unlikely scenario of naming conflict with a Built-in, the Built-in will take prescedence
     pub fn new(step_words: Vec<String>) -> Result<Step> {
+        //`env` only lists the variables, running a command with it is left to the external program,
+        // which inherits the exported variables
+        let runs_command = step_words.first().map(String::as_str) == Some("env")
+            && step_words.iter().skip(1).any(|w| !w.contains('='));
         let mut words = step_words.into_iter().peekable();
 
         if words.peek().is_none() {
@@ -48,7 +118,7 @@ impl Step {
         }
 
         //Check if builtin with that name exists
-        if Builtin::exists(words.peek().unwrap()) {
+        if Builtin::exists(words.peek().unwrap()) && !runs_command {
             let b = Step::parse_builtin(words)?;
             Ok(Step::Builtin(b))
         } else {
@@ -63,19 +133,9 @@ impl Step {
         mut words: std::iter::Peekable<std::vec::IntoIter<std::string::String>>,
     ) -> Result<Command> {
         let mut command = Command::new(words.next().unwrap());
-
-        for w in words {
-            match w {
-                //There can be no arguments after the beginning of redirection
-                _ if redirection::Redirection::is_redirection(&w) => {
-                    break;
-                }
-                //Arguments
-                _ => {
-                    command.arg(&w);
-                }
-            }
-        }
+        command.env_clear().envs(state::exported_variables());
+        //Redirections were already taken out, e.g. by the parser, what's left are arguments, even `>`
+        command.args(words);
         Ok(command)
     }
 
@@ -87,39 +147,136 @@ impl Step {
         let mut b_in = Builtin::new(&words.next().unwrap());
 
         for w in words {
-            match w {
-                //There can be no arguments after the beginning of redirection
-                _ if crate::redirection::Redirection::is_redirection(&w) => {
-                    break;
-                }
-                //Arguments
-                _ => {
-                    b_in = b_in.arg(&w);
-                }
-            }
+            b_in = b_in.arg(&w);
         }
         Ok(b_in)
     }
+}
 
-    /// Runs the Step
-    /// Err if the Step couldn't run
-    pub fn run(self, stdin: &[u8]) -> Result<StepOutput> {
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Step::Command(mut c) => {
-                let mut process = c
-                    .stdin(Stdio::piped())
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::piped())
-                    .spawn()?;
-                process.stdin.as_ref().unwrap().write_all(stdin)?;
-                process.wait().unwrap();
-                Ok(StepOutput::from(process.wait_with_output()?))
+            Step::Command(c) => {
+                write!(f, "{}", c.get_program().to_string_lossy())?;
+                for arg in c.get_args() {
+                    write!(f, " {}", arg.to_string_lossy())?;
+                }
+                Ok(())
+            }
+            Step::Builtin(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+/// Starts the process of a Command Step, with its standard streams connected to the given `Stdio`s.
+/// The `other_fds` are set up by the process afterwards: each fd becomes a copy of the given one, or is closed if
+/// `None`
+///
+/// With job control enabled the process joins the process group `pgid`, or starts its own when `None`,
+/// and takes the terminal if in the `foreground`
+pub fn spawn(
+    mut command: Command,
+    [stdin, stdout, stderr]: [Stdio; 3],
+    other_fds: Vec<(RawFd, Option<OwnedFd>)>,
+    pgid: Option<Pid>,
+    foreground: bool,
+) -> Result<Child> {
+    command.stdin(stdin).stdout(stdout).stderr(stderr);
+
+    if !other_fds.is_empty() {
+        //Moved above all the fds to set up, so that setting one up doesn't overwrite the source of another
+        let lowest = other_fds.iter().map(|(fd, _)| fd + 1).max().unwrap_or(0);
+        let mut fds = Vec::with_capacity(other_fds.len());
+        for (fd, src) in other_fds {
+            let src = match src {
+                Some(src) => {
+                    let raised = fcntl(src.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(lowest))?;
+                    // Safety: the new fd was just created, nothing else owns it
+                    Some(unsafe { OwnedFd::from_raw_fd(raised) })
+                }
+                None => None,
+            };
+            fds.push((fd, src));
+        }
+        // Safety: only async-signal-safe functions are called between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                for (fd, src) in &fds {
+                    match src {
+                        Some(src) => {
+                            dup2(src.as_raw_fd(), *fd)?;
+                        }
+                        None => match close(*fd) {
+                            Ok(()) | Err(Errno::EBADF) => {}
+                            Err(e) => return Err(e.into()),
+                        },
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+
+    let terminal = job::terminal();
+    let group = pgid.unwrap_or_else(|| Pid::from_raw(0));
+    // Safety: only async-signal-safe functions are called between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(terminal) = terminal {
+                setpgid(Pid::from_raw(0), group)?;
+                if foreground {
+                    tcsetpgrp(terminal, getpgrp())?;
+                }
             }
-            Step::Builtin(b) => b.run(stdin),
+            //After taking the terminal, which needs SIGTTOU ignored
+            signal::restore_defaults()
+        });
+    }
+
+    let child = command.spawn()?;
+    if terminal.is_some() {
+        //Also done by the parent, so the group exists before it's used by the next Step or given the terminal.
+        //Fails if the child already exec'd, which means it has done it itself
+        let pid = Pid::from_raw(child.id() as i32);
+        let _ = setpgid(pid, pgid.unwrap_or(pid));
+    }
+    Ok(child)
+}
+
+/// Runs `run` in a child process, a copy of the shell, which exits with the status `run` returns. Used for what
+/// must not change the state of the shell itself, e.g. a Builtin that isn't the last Step of a Pipeline (`cd x | cat`)
+///
+/// Joins the process group `pgid` and takes the terminal like `spawn` does. Pipelines started by the child stay in
+/// its process group, as job control is disabled inside of it
+pub fn fork(run: impl FnOnce() -> i32, pgid: Option<Pid>, foreground: bool) -> Result<Pid> {
+    let terminal = job::terminal();
+    // Safety: the other threads of the shell only copy between file descriptors, the child needs none of their locks
+    match unsafe { fork_process() }? {
+        ForkResult::Child => {
+            if let Some(terminal) = terminal {
+                let _ = setpgid(Pid::from_raw(0), pgid.unwrap_or_else(|| Pid::from_raw(0)));
+                if foreground {
+                    let _ = tcsetpgrp(terminal, getpgrp());
+                }
+                job::disable();
+            }
+            let _ = signal::restore_defaults();
+            let code = run();
+            // Safety: skips the exit handlers and buffers of the shell, which still belong to the parent
+            unsafe { nix::libc::_exit(code) }
+        }
+        ForkResult::Parent { child } => {
+            if terminal.is_some() {
+                //Like `spawn`, so the group exists before it's used by the next Step or given the terminal
+                let _ = setpgid(child, pgid.unwrap_or(child));
+            }
+            Ok(child)
         }
     }
 }
 
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test {
     use super::*;
     #[test]
@@ -139,4 +296,14 @@ mod test {
             assert_eq!(&broa.args, &vec![String::from("/home/user")]);
         }
     }
+
+    #[test]
+    fn redirection_words_are_arguments() {
+        let words = ["echo", ">", "a"].iter().map(|w| w.to_string()).collect();
+        if let Step::Builtin(b) = Step::new(words).unwrap() {
+            assert_eq!(b.args, vec![">", "a"]);
+        } else {
+            panic!("echo is a built-in");
+        }
+    }
 }